@@ -0,0 +1,79 @@
+//! The bytecode that `compiler` produces and `vm` executes.
+//!
+//! Each function has its own code, constant pool and nested function prototypes. Its
+//! frame has a fixed number of local slots: the parameters first, then the bindings of
+//! `let*` and `catch*`, which reuse slots once their scope ends. Temporaries are pushed
+//! on the stack above the slots.
+
+use std::rc::Rc;
+
+use crate::value::Value;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    /// Pushes a value from the constant pool.
+    Constant(u32),
+    Nil,
+    GetLocal(u16),
+    /// Pushes the content of the box in a slot. Locals that closures may use before they
+    /// are initialized, like in `(let* (f (fn* [] (f))) ...)`, are boxed.
+    GetBoxedLocal(u16),
+    GetUpvalue(u16),
+    GetBoxedUpvalue(u16),
+    /// Looks up the symbol in the constant pool in the global environment.
+    GetGlobal(u32),
+    /// Pops a value into a slot.
+    SetLocal(u16),
+    /// Puts a new, empty box into a slot.
+    NewBox(u16),
+    /// Pops a value into the box in a slot.
+    SetBox(u16),
+    /// Binds the symbol in the constant pool to the value on top of the stack.
+    DefGlobal(u32),
+    /// Like `DefGlobal`, but the value must be a function, which becomes a macro.
+    DefMacro(u32),
+    Pop,
+    Jump(u32),
+    /// Pops a value and jumps if it is `false` or `nil`.
+    JumpIfFalse(u32),
+    /// Calls the function below the given number of arguments.
+    Call(u16),
+    /// Like `Call`, but replaces the current frame.
+    TailCall(u16),
+    Return,
+    /// Creates a closure from a nested function prototype.
+    Closure(u32),
+    /// Creates a lazy sequence that calls a closure of a nested function prototype.
+    LazySeq(u32),
+    /// Collects the given number of values into a vector, map (key-value pairs) or set.
+    Vector(u32),
+    Map(u32),
+    Set(u32),
+    /// Installs an error handler that continues at the given instruction, with the
+    /// error on the stack.
+    Try(u32),
+    /// Removes the innermost error handler.
+    EndTry,
+}
+
+/// Where a closure gets an upvalue from when it is created: a slot or an upvalue of the
+/// enclosing function.
+#[derive(Clone, Copy, Debug)]
+pub enum Capture {
+    Local(u16),
+    Upvalue(u16),
+}
+
+#[derive(Debug, Default)]
+pub struct Function {
+    pub code: Vec<Op>,
+    pub constants: Vec<Value>,
+    pub functions: Vec<Rc<Function>>,
+    pub captures: Vec<Capture>,
+    /// The number of parameters, not counting the rest parameter.
+    pub arity: usize,
+    /// Whether the remaining arguments are collected into a list after the parameters.
+    pub variadic: bool,
+    /// The number of local slots, including the parameters.
+    pub slots: usize,
+}
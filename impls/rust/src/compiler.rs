@@ -0,0 +1,653 @@
+//! Compiles forms to bytecode.
+//!
+//! Special forms are recognized and macros are expanded once, when a form is compiled,
+//! instead of every time it is evaluated. Locals bound by `fn*`, `let*` and `catch*` are
+//! resolved to slots, and closures capture the locals of enclosing functions they use as
+//! upvalues, so only globals are looked up by name at runtime.
+//!
+//! Macros are expanded when the top-level form containing them is compiled, so the body of
+//! a `fn*` is expanded once, however often it is called. As a consequence, redefining a
+//! macro only affects forms compiled afterwards: functions defined before keep the old
+//! expansion, and a function that uses a macro before it is defined calls it like a
+//! function. The forms of a top-level `do`, like a file loaded by `load-file`, are compiled
+//! one at a time, so each of them can use the macros defined by the ones before.
+
+use std::{cell::RefCell, ops::RangeBounds, rc::Rc};
+
+use crate::{
+    bytecode::{Capture, Function, Op},
+    env::Env,
+    eval_fn_no_tco,
+    runtime_errors::{self, RuntimeResult},
+    symbol::Symbol,
+    value::{Closure, Value},
+};
+
+/// Compiles a top-level form to a function without parameters.
+pub fn compile(form: Value, env: &Rc<RefCell<Env>>) -> RuntimeResult<Rc<Function>> {
+    let mut compiler = Compiler {
+        env,
+        functions: vec![FunctionState::default()],
+    };
+    compiler.expr(form, true)?;
+    Ok(Rc::new(compiler.finish_function()))
+}
+
+/// Expands a top-level form while it is a macro call.
+pub fn macroexpand(form: Value, env: &Rc<RefCell<Env>>) -> RuntimeResult<Value> {
+    Compiler {
+        env,
+        functions: vec![FunctionState::default()],
+    }
+    .macroexpand(form)
+}
+
+struct Local {
+    name: Symbol,
+    slot: u16,
+    boxed: bool,
+    /// Whether the local is initialized. Bindings of a `let*` are declared before they are
+    /// initialized, so that closures in earlier bindings can refer to later ones.
+    bound: bool,
+}
+
+#[derive(Default)]
+struct FunctionState {
+    function: Function,
+    locals: Vec<Local>,
+    /// The names of the upvalues, and whether they are boxed.
+    upvalues: Vec<(Symbol, bool)>,
+}
+
+enum Resolved {
+    Local(u16, bool),
+    Upvalue(u16, bool),
+    Global,
+}
+
+struct Compiler<'a> {
+    env: &'a Rc<RefCell<Env>>,
+    /// The function being compiled, and the functions it is nested in.
+    functions: Vec<FunctionState>,
+}
+
+impl Compiler<'_> {
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().unwrap()
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        let code = &mut self.current().function.code;
+        code.push(op);
+        code.len() - 1
+    }
+
+    fn constant(&mut self, value: Value) -> u32 {
+        let constants = &mut self.current().function.constants;
+        constants.push(value);
+        (constants.len() - 1) as u32
+    }
+
+    /// Points the jump at `at` to the next instruction.
+    fn patch_jump(&mut self, at: usize) {
+        let target = self.current().function.code.len() as u32;
+        match &mut self.current().function.code[at] {
+            Op::Jump(t) | Op::JumpIfFalse(t) | Op::Try(t) => *t = target,
+            _ => unreachable!(),
+        }
+    }
+
+    fn declare_local(&mut self, name: Value, boxed: bool, bound: bool) -> RuntimeResult<u16> {
+        let name = name.try_into_env_map_key()?;
+        let state = self.current();
+        let slot = state.locals.len() as u16;
+        state.locals.push(Local {
+            name,
+            slot,
+            boxed,
+            bound,
+        });
+        state.function.slots = state.function.slots.max(state.locals.len());
+        Ok(slot)
+    }
+
+    fn end_scope(&mut self, len: usize) {
+        self.current().locals.truncate(len);
+    }
+
+    fn resolve(&mut self, name: &Symbol) -> Resolved {
+        let depth = self.functions.len() - 1;
+        if let Some(local) = find_local(&self.functions[depth], name, true) {
+            return Resolved::Local(local.slot, local.boxed);
+        }
+        match self.resolve_upvalue(depth, name) {
+            Some((index, boxed)) => Resolved::Upvalue(index, boxed),
+            None => Resolved::Global,
+        }
+    }
+
+    fn resolve_upvalue(&mut self, depth: usize, name: &Symbol) -> Option<(u16, bool)> {
+        if depth == 0 {
+            return None;
+        }
+        let state = &self.functions[depth];
+        if let Some(i) = state.upvalues.iter().position(|(n, _)| n == name) {
+            return Some((i as u16, state.upvalues[i].1));
+        }
+        let (capture, boxed) = match find_local(&self.functions[depth - 1], name, false) {
+            Some(local) => (Capture::Local(local.slot), local.boxed),
+            None => {
+                let (index, boxed) = self.resolve_upvalue(depth - 1, name)?;
+                (Capture::Upvalue(index), boxed)
+            }
+        };
+        let state = &mut self.functions[depth];
+        state.upvalues.push((name.clone(), boxed));
+        state.function.captures.push(capture);
+        Some(((state.upvalues.len() - 1) as u16, boxed))
+    }
+
+    /// The macro a form calls, unless its head is shadowed by a local.
+    fn macro_call(&mut self, form: &Value) -> Option<Rc<Closure>> {
+        let name = match form {
+            Value::List(l, _) => match l.first() {
+                Some(Value::Symbol(name)) => name,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        if !matches!(self.resolve(name), Resolved::Global) {
+            return None;
+        }
+        match Env::get(self.env, name) {
+            Ok(Value::Closure(c, _)) if c.is_macro => Some(c),
+            _ => None,
+        }
+    }
+
+    fn macroexpand(&mut self, mut form: Value) -> RuntimeResult<Value> {
+        while let Some(closure) = self.macro_call(&form) {
+            let args = form.into_list().into_iter().skip(1).collect();
+            form = eval_fn_no_tco(
+                Value::Closure(closure, Box::new(Value::Nil)),
+                args,
+                self.env.clone(),
+            )?;
+        }
+        Ok(form)
+    }
+
+    /// Whether a closure created by a form may use the symbol: whether it appears in a `fn*`
+    /// or `lazy-seq` form. A macro call may expand to such a form using any symbol, even one
+    /// that doesn't appear in the call, so it may capture anything.
+    fn may_capture(&self, form: &Value, name: &Symbol) -> bool {
+        match form {
+            Value::List(l, _) => match l.first() {
+                Some(Value::Symbol(head)) if head == "fn*" || head == "lazy-seq" => {
+                    mentions(form, name)
+                }
+                Some(Value::Symbol(head))
+                    if matches!(
+                        Env::get(self.env, head),
+                        Ok(Value::Closure(c, _)) if c.is_macro
+                    ) =>
+                {
+                    true
+                }
+                _ => l.iter().any(|v| self.may_capture(v, name)),
+            },
+            Value::Vec(v, _) => v.iter().any(|v| self.may_capture(v, name)),
+            Value::Map(map, _) => map
+                .iter()
+                .any(|(k, v)| self.may_capture(k, name) || self.may_capture(v, name)),
+            Value::Set(set, _) => set.iter().any(|v| self.may_capture(v, name)),
+            _ => false,
+        }
+    }
+
+    fn expr(&mut self, form: Value, tail: bool) -> RuntimeResult<()> {
+        let form = self.macroexpand(form)?;
+        match form {
+            Value::Symbol(name) => {
+                let op = match self.resolve(&name) {
+                    Resolved::Local(slot, false) => Op::GetLocal(slot),
+                    Resolved::Local(slot, true) => Op::GetBoxedLocal(slot),
+                    Resolved::Upvalue(index, false) => Op::GetUpvalue(index),
+                    Resolved::Upvalue(index, true) => Op::GetBoxedUpvalue(index),
+                    Resolved::Global => Op::GetGlobal(self.constant(Value::Symbol(name))),
+                };
+                self.emit(op);
+            }
+            Value::List(l, meta) if l.is_empty() => {
+                let c = self.constant(Value::List(l, meta));
+                self.emit(Op::Constant(c));
+            }
+            Value::List(l, _) => self.list(l, tail)?,
+            // a form built by lazy sequence functions, like `(eval (map ...))`
+            seq @ Value::LazySeq(_, _) => {
+                let form = Value::List(seq.try_into_list_or_vec()?, Box::new(Value::Nil));
+                self.expr(form, tail)?;
+            }
+            coll @ (Value::Vec(_, _) | Value::Map(_, _) | Value::Set(_, _))
+                if !is_constant(&coll) =>
+            {
+                let op = match &coll {
+                    Value::Vec(v, _) => Op::Vector(v.len() as u32),
+                    Value::Map(map, _) => Op::Map(map.len() as u32),
+                    _ => Op::Set(coll.seq_count()? as u32),
+                };
+                match coll {
+                    Value::Map(map, _) => {
+                        for (k, v) in map.iter() {
+                            self.expr(k.clone(), false)?;
+                            self.expr(v.clone(), false)?;
+                        }
+                    }
+                    coll => {
+                        for v in coll.seq_elements()? {
+                            self.expr(v, false)?;
+                        }
+                    }
+                }
+                self.emit(op);
+            }
+            v => {
+                let c = self.constant(v);
+                self.emit(Op::Constant(c));
+            }
+        }
+        Ok(())
+    }
+
+    fn list(&mut self, l: Vec<Value>, tail: bool) -> RuntimeResult<()> {
+        let special_form = match &l[0] {
+            Value::Symbol(name) => name.clone(),
+            _ => Symbol::new(""),
+        };
+        let special_form = special_form.as_str();
+        match special_form {
+            "def!" | "defmacro!" => {
+                ensure_arity(&l, special_form, 2..=2)?;
+                let mut args = l.into_iter();
+                let name = args.nth(1).unwrap();
+                name.clone().try_into_env_map_key()?;
+                self.expr(args.next().unwrap(), false)?;
+                let c = self.constant(name);
+                self.emit(if special_form == "def!" {
+                    Op::DefGlobal(c)
+                } else {
+                    Op::DefMacro(c)
+                });
+            }
+            "let*" => {
+                ensure_arity(&l, special_form, 1..)?;
+                let mut args = l.into_iter().skip(1);
+                let bindings = args.next().unwrap().try_into_list_or_vec()?;
+                if bindings.len() % 2 != 0 {
+                    return Err(runtime_errors::error_to_string(
+                        "let* requires an even number of forms in bindings",
+                    ));
+                }
+                let scope = self.current().locals.len();
+                let inits: Vec<_> = bindings.iter().skip(1).step_by(2).collect();
+                let mut slots = Vec::new();
+                for (i, name) in bindings.iter().step_by(2).enumerate() {
+                    let name_str = name.clone().try_into_env_map_key()?;
+                    // closures in this or earlier bindings may use the local before it
+                    // is initialized, so it has to be shared through a box
+                    let boxed = inits[..=i]
+                        .iter()
+                        .any(|init| self.may_capture(init, &name_str));
+                    let slot = self.declare_local(name.clone(), boxed, false)?;
+                    if boxed {
+                        self.emit(Op::NewBox(slot));
+                    }
+                    slots.push(slot);
+                }
+                for (i, init) in bindings.into_iter().skip(1).step_by(2).enumerate() {
+                    self.expr(init, false)?;
+                    let local = &mut self.current().locals[scope + i];
+                    local.bound = true;
+                    let op = if local.boxed {
+                        Op::SetBox(slots[i])
+                    } else {
+                        Op::SetLocal(slots[i])
+                    };
+                    self.emit(op);
+                }
+                self.body(args, tail)?;
+                self.end_scope(scope);
+            }
+            "do" => self.body(l.into_iter().skip(1), tail)?,
+            "if" => {
+                ensure_arity(&l, special_form, 2..=3)?;
+                let mut args = l.into_iter().skip(1);
+                self.expr(args.next().unwrap(), false)?;
+                let to_else = self.emit(Op::JumpIfFalse(0));
+                self.expr(args.next().unwrap(), tail)?;
+                let to_end = self.emit(Op::Jump(0));
+                self.patch_jump(to_else);
+                match args.next() {
+                    Some(else_branch) => self.expr(else_branch, tail)?,
+                    None => {
+                        self.emit(Op::Nil);
+                    }
+                }
+                self.patch_jump(to_end);
+            }
+            "fn*" => {
+                ensure_arity(&l, special_form, 1..)?;
+                let mut args = l.into_iter().skip(1);
+                let params = args.next().unwrap().try_into_list_or_vec()?;
+                let index = self.function(params, args)?;
+                self.emit(Op::Closure(index));
+            }
+            "lazy-seq" => {
+                let index = self.function(Vec::new(), l.into_iter().skip(1))?;
+                self.emit(Op::LazySeq(index));
+            }
+            "quote" => {
+                ensure_arity(&l, special_form, 1..=1)?;
+                let c = self.constant(l.into_iter().nth(1).unwrap());
+                self.emit(Op::Constant(c));
+            }
+            "quasiquoteexpand" => {
+                ensure_arity(&l, special_form, 1..=1)?;
+                let c = self.constant(quasiquote(l.into_iter().nth(1).unwrap())?);
+                self.emit(Op::Constant(c));
+            }
+            "quasiquote" => {
+                ensure_arity(&l, special_form, 1..=1)?;
+                self.expr(quasiquote(l.into_iter().nth(1).unwrap())?, tail)?;
+            }
+            "macroexpand" => {
+                ensure_arity(&l, special_form, 1..=1)?;
+                let expanded = self.macroexpand(l.into_iter().nth(1).unwrap())?;
+                let c = self.constant(expanded);
+                self.emit(Op::Constant(c));
+            }
+            "try*" => {
+                ensure_arity(&l, special_form, 1..=2)?;
+                let mut args = l.into_iter().skip(1);
+                let body = args.next().unwrap();
+                let catch = match args.next() {
+                    Some(catch) => catch,
+                    None => return self.expr(body, tail),
+                };
+                let catch = match catch {
+                    Value::List(l, _)
+                        if l.len() == 3 && matches!(&l[0], Value::Symbol(s) if s == "catch*") =>
+                    {
+                        l
+                    }
+                    v => return Err(runtime_errors::not_a("(catch* name body)", &v)),
+                };
+                let to_catch = self.emit(Op::Try(0));
+                self.expr(body, false)?;
+                self.emit(Op::EndTry);
+                let to_end = self.emit(Op::Jump(0));
+                self.patch_jump(to_catch);
+                let scope = self.current().locals.len();
+                let mut catch = catch.into_iter().skip(1);
+                let slot = self.declare_local(catch.next().unwrap(), false, true)?;
+                self.emit(Op::SetLocal(slot));
+                self.expr(catch.next().unwrap(), tail)?;
+                self.end_scope(scope);
+                self.patch_jump(to_end);
+            }
+            _ => {
+                let argc = l.len() - 1;
+                for v in l {
+                    self.expr(v, false)?;
+                }
+                self.emit(if tail {
+                    Op::TailCall(argc as u16)
+                } else {
+                    Op::Call(argc as u16)
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles forms that are evaluated in order, with the value of the last one.
+    fn body(&mut self, forms: impl Iterator<Item = Value>, tail: bool) -> RuntimeResult<()> {
+        let mut forms = forms.peekable();
+        if forms.peek().is_none() {
+            self.emit(Op::Nil);
+        }
+        while let Some(form) = forms.next() {
+            let last = forms.peek().is_none();
+            self.expr(form, tail && last)?;
+            if !last {
+                self.emit(Op::Pop);
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles a nested function and returns the index of its prototype.
+    fn function(
+        &mut self,
+        params: Vec<Value>,
+        body: impl Iterator<Item = Value>,
+    ) -> RuntimeResult<u32> {
+        self.functions.push(FunctionState::default());
+        let mut params = params.into_iter();
+        while let Some(param) = params.next() {
+            if matches!(&param, Value::Symbol(s) if s == "&") {
+                let rest = params
+                    .next()
+                    .ok_or_else(|| runtime_errors::error_to_string("missing parameter after &"))?;
+                self.declare_local(rest, false, true)?;
+                self.current().function.variadic = true;
+                break;
+            }
+            self.declare_local(param, false, true)?;
+            self.current().function.arity += 1;
+        }
+        self.body(body, true)?;
+        let function = self.finish_function();
+        let functions = &mut self.current().function.functions;
+        functions.push(Rc::new(function));
+        Ok((functions.len() - 1) as u32)
+    }
+
+    fn finish_function(&mut self) -> Function {
+        self.emit(Op::Return);
+        self.functions.pop().unwrap().function
+    }
+}
+
+/// Finds the innermost local with a name. Locals that are not initialized yet are only
+/// visible to nested functions, which can use them through their box.
+fn find_local<'a>(state: &'a FunctionState, name: &Symbol, direct: bool) -> Option<&'a Local> {
+    state
+        .locals
+        .iter()
+        .rev()
+        .find(|l| l.name == *name && (l.bound || (!direct && l.boxed)))
+}
+
+/// Whether a form contains the symbol anywhere.
+fn mentions(form: &Value, name: &Symbol) -> bool {
+    match form {
+        Value::Symbol(s) => s == name,
+        Value::List(_, _) | Value::Vec(_, _) => form
+            .try_iter_list_or_vec()
+            .unwrap()
+            .any(|v| mentions(v, name)),
+        Value::Map(_, _) => form
+            .try_iter_map()
+            .unwrap()
+            .any(|(k, v)| mentions(k, name) || mentions(v, name)),
+        Value::Set(_, _) => form.try_iter_set().unwrap().any(|v| mentions(v, name)),
+        _ => false,
+    }
+}
+
+/// Whether a form evaluates to itself.
+fn is_constant(form: &Value) -> bool {
+    match form {
+        Value::Symbol(_) | Value::List(_, _) | Value::LazySeq(_, _) => false,
+        Value::Vec(_, _) => form.try_iter_list_or_vec().unwrap().all(is_constant),
+        Value::Map(_, _) => form
+            .try_iter_map()
+            .unwrap()
+            .all(|(k, v)| is_constant(k) && is_constant(v)),
+        Value::Set(_, _) => form.try_iter_set().unwrap().all(is_constant),
+        _ => true,
+    }
+}
+
+fn ensure_arity(form: &[Value], name: &str, arity: impl RangeBounds<usize>) -> RuntimeResult<()> {
+    if arity.contains(&(form.len() - 1)) {
+        Ok(())
+    } else {
+        Err(runtime_errors::wrong_arity(name, form.len() - 1))
+    }
+}
+
+fn quasiquote(ast: Value) -> RuntimeResult<Value> {
+    match ast {
+        Value::List(l, _) if matches!(l.first(), Some(Value::Symbol(n)) if n == "unquote") => {
+            Ok(l.into_iter().nth(1).unwrap())
+        }
+        Value::List(l, _) => process_list(l),
+        Value::Vec(ast, _) => Ok(Value::List(
+            vec![
+                Value::Symbol("vec".into()),
+                process_list(ast.iter().cloned().collect())?,
+            ],
+            Box::new(Value::Nil),
+        )),
+        v @ Value::Map(_, _) | v @ Value::Set(_, _) | v @ Value::Symbol(_) => Ok(Value::List(
+            vec![Value::Symbol("quote".into()), v],
+            Box::new(Value::Nil),
+        )),
+        v => Ok(v),
+    }
+}
+
+fn process_list(list: Vec<Value>) -> RuntimeResult<Value> {
+    let mut result = Vec::new();
+    for elt in list.into_iter().rev() {
+        result = match elt {
+            Value::List(l, meta) if matches!(l.first(), Some(Value::Symbol(n)) if n == "splice-unquote") =>
+            {
+                vec![
+                    Value::Symbol("concat".into()),
+                    l.into_iter().nth(1).unwrap(),
+                    Value::List(result, meta),
+                ]
+            }
+            v => {
+                vec![
+                    Value::Symbol("cons".into()),
+                    quasiquote(v)?,
+                    Value::List(result, Box::new(Value::Nil)),
+                ]
+            }
+        }
+    }
+    Ok(Value::List(result, Box::new(Value::Nil)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+    use crate::{mal_core, reader::read_str, vm::eval};
+
+    fn core_env() -> Rc<RefCell<Env>> {
+        let env = Rc::new(RefCell::new(Env::new(None)));
+        mal_core::init_env(&mut env.borrow_mut());
+        env
+    }
+
+    fn eval_all(forms: &str) -> Value {
+        eval(read_str(&format!("(do {})", forms)).unwrap(), core_env()).unwrap()
+    }
+
+    #[test]
+    fn locals_are_addressed_by_slot() {
+        let env = core_env();
+        let f = compile(
+            read_str("(fn* [a] (let* (b a) (fn* [] (+ a b))))").unwrap(),
+            &env,
+        )
+        .unwrap();
+        let outer = &f.functions[0];
+        assert_eq!(
+            outer.code,
+            [Op::GetLocal(0), Op::SetLocal(1), Op::Closure(0), Op::Return]
+        );
+        let inner = &outer.functions[0];
+        assert_eq!(
+            inner.code,
+            [
+                Op::GetGlobal(0),
+                Op::GetUpvalue(0),
+                Op::GetUpvalue(1),
+                Op::TailCall(2),
+                Op::Return
+            ]
+        );
+        // only bindings that a closure may use before they are initialized are boxed
+        let f = compile(
+            read_str("(let* (a 1) (let* (a (+ a 1) f (fn* [] (g)) g (fn* [] a)) (f)))").unwrap(),
+            &env,
+        )
+        .unwrap();
+        let boxes: Vec<_> = f
+            .code
+            .iter()
+            .filter(|op| matches!(op, Op::NewBox(_)))
+            .collect();
+        assert_eq!(boxes, [&Op::NewBox(3)]);
+    }
+
+    #[test]
+    fn macros_are_expanded_once() {
+        let expansions = eval_all(
+            "(def! n (atom 0))
+             (defmacro! m (fn* [] (do (swap! n + 1) 1)))
+             (def! f (fn* [] (m)))
+             (f) (f) (f)
+             @n",
+        );
+        assert_eq!(expansions, read_str("1").unwrap());
+    }
+
+    #[test]
+    fn redefining_macros() {
+        let results = eval_all(
+            "(defmacro! m (fn* [] 1))
+             (def! f (fn* [] (m)))
+             (defmacro! m (fn* [] 2))
+             (list (f) (m))",
+        );
+        assert_eq!(results, read_str("(1 2)").unwrap());
+        // used before it was defined, the macro is called like a function
+        let result = eval_all(
+            "(def! f (fn* [] (m 1)))
+             (defmacro! m (fn* [x] (list 'inc x)))
+             (f)",
+        );
+        assert_eq!(result, read_str("(inc 1)").unwrap());
+        // locals shadow macros
+        let result = eval_all("(defmacro! m (fn* [] 1)) (let* (m (fn* [] 2)) (m))");
+        assert_eq!(result, read_str("2").unwrap());
+    }
+
+    #[test]
+    fn macros_may_capture_later_bindings() {
+        // the expansion uses `g`, which doesn't appear in the macro call
+        let result = eval_all(
+            "(defmacro! later (fn* [] (list 'fn* [] (symbol \"g\"))))
+             (let* (f (later) g 5) (f))",
+        );
+        assert_eq!(result, read_str("5").unwrap());
+    }
+}
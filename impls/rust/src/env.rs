@@ -1,12 +1,13 @@
 use rustc_hash::FxHashMap;
 
 use crate::runtime_errors::{self, RuntimeResult};
+use crate::symbol::Symbol;
 use crate::Value;
 use std::{cell::RefCell, rc::Rc};
 
 #[derive(Debug)]
 pub struct Env {
-    pub data: FxHashMap<String, Value>,
+    pub data: FxHashMap<Symbol, Value>,
     outer: Option<Rc<RefCell<Env>>>,
 }
 
@@ -18,36 +19,11 @@ impl Env {
         }
     }
 
-    pub fn new_with_binds(
-        outer: Option<Rc<RefCell<Env>>>,
-        mut binds: impl Iterator<Item = Value>,
-        mut exprs: impl Iterator<Item = Value>,
-    ) -> RuntimeResult<Self> {
-        let mut env = Self {
-            outer,
-            data: FxHashMap::default(),
-        };
-        while let Some(key) = binds.next() {
-            if matches!(&key, Value::Symbol(s) if s == "&") {
-                let key = binds.next().unwrap();
-                let values = exprs.collect();
-                env.set(
-                    key.try_into_env_map_key()?,
-                    Value::List(values, Box::new(Value::Nil)),
-                );
-                break;
-            } else {
-                env.set(key.try_into_env_map_key()?, exprs.next().unwrap())
-            }
-        }
-        Ok(env)
-    }
-
-    pub fn set(&mut self, key: impl Into<String>, value: Value) {
+    pub fn set(&mut self, key: impl Into<Symbol>, value: Value) {
         self.data.insert(key.into(), value);
     }
 
-    pub fn find(env: &Rc<RefCell<Env>>, key: &str) -> Option<Rc<RefCell<Env>>> {
+    pub fn find(env: &Rc<RefCell<Env>>, key: &Symbol) -> Option<Rc<RefCell<Env>>> {
         if env.as_ref().borrow().data.contains_key(key) {
             Some(env.clone())
         } else if let Some(outer) = &env.as_ref().borrow().outer {
@@ -57,14 +33,51 @@ impl Env {
         }
     }
 
-    pub fn get(env: &Rc<RefCell<Env>>, key: &str) -> RuntimeResult<Value> {
-        Ok(Self::find(env, key)
-            .ok_or_else(|| runtime_errors::error_to_string(format!("'{}' not found", key)))?
-            .as_ref()
-            .borrow()
-            .data
-            .get(key)
-            .unwrap()
-            .clone())
+    /// The outermost environment, which holds the core functions.
+    pub fn base(env: &Rc<RefCell<Env>>) -> Rc<RefCell<Env>> {
+        match &env.as_ref().borrow().outer {
+            Some(outer) => Self::base(outer),
+            None => env.clone(),
+        }
+    }
+
+    /// Looks up a symbol. `core/name` refers to `name` in the base environment, so core
+    /// functions stay reachable when they are shadowed.
+    pub fn get(env: &Rc<RefCell<Env>>, key: &Symbol) -> RuntimeResult<Value> {
+        if let Some(found) = Self::find(env, key) {
+            return Ok(found.as_ref().borrow().data.get(key).unwrap().clone());
+        }
+        key.strip_prefix("core/")
+            .and_then(|name| {
+                Self::base(env)
+                    .as_ref()
+                    .borrow()
+                    .data
+                    .get(&Symbol::new(name))
+                    .cloned()
+            })
+            .ok_or_else(|| runtime_errors::error_to_string(format!("'{}' not found", key)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shadowing_the_base_environment() {
+        let base = Rc::new(RefCell::new(Env::new(None)));
+        base.borrow_mut().set("count", Value::Number(1usize.into()));
+        let env = Rc::new(RefCell::new(Env::new(Some(base.clone()))));
+        let count = Symbol::new("count");
+        assert_eq!(Env::get(&env, &count), Ok(Value::Number(1usize.into())));
+        env.borrow_mut().set("count", Value::Nil);
+        assert_eq!(Env::get(&env, &count), Ok(Value::Nil));
+        assert_eq!(
+            Env::get(&env, &Symbol::new("core/count")),
+            Ok(Value::Number(1usize.into()))
+        );
+        assert_eq!(Env::get(&base, &count), Ok(Value::Number(1usize.into())));
+        assert!(Env::get(&env, &Symbol::new("core/nope")).is_err());
     }
 }
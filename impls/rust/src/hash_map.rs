@@ -0,0 +1,460 @@
+//! A persistent hash map, implemented as a hash array mapped trie (HAMT) like Clojure's
+//! `PersistentHashMap`.
+//!
+//! Each level of the trie consumes 5 bits of the key's hash. Branches only store the
+//! entries that are present, with a bitmap telling which ones these are. Keys whose hashes
+//! are completely equal end up in a collision node. Like in `vector`, updates copy only the
+//! path to the changed entry, and nodes that aren't shared are updated in place.
+
+use std::{
+    borrow::Borrow,
+    fmt,
+    hash::{Hash, Hasher},
+    iter::FromIterator,
+    mem,
+    rc::Rc,
+};
+
+use rustc_hash::FxHasher;
+
+const BITS: u32 = 5;
+const MASK: u64 = (1 << BITS) - 1;
+
+#[derive(Clone)]
+enum Entry<K, V> {
+    Leaf { hash: u64, key: K, value: V },
+    Node(Rc<Node<K, V>>),
+}
+
+#[derive(Clone)]
+enum Node<K, V> {
+    Branch {
+        bitmap: u32,
+        entries: Vec<Entry<K, V>>,
+    },
+    Collision {
+        hash: u64,
+        pairs: Vec<(K, V)>,
+    },
+}
+
+fn hash_of<Q: Hash + ?Sized>(key: &Q) -> u64 {
+    let mut hasher = FxHasher::default();
+    key.hash(&mut hasher);
+    hasher.finish()
+}
+
+fn bit(hash: u64, shift: u32) -> u32 {
+    1 << ((hash >> shift) & MASK)
+}
+
+impl<K: Clone + Eq, V: Clone> Node<K, V> {
+    fn empty() -> Self {
+        Node::Branch {
+            bitmap: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    fn get<Q>(&self, shift: u32, hash: u64, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        match self {
+            Node::Branch { bitmap, entries } => {
+                let bit = bit(hash, shift);
+                if bitmap & bit == 0 {
+                    return None;
+                }
+                match &entries[(bitmap & (bit - 1)).count_ones() as usize] {
+                    Entry::Leaf { key: k, value, .. } if k.borrow() == key => Some((k, value)),
+                    Entry::Leaf { .. } => None,
+                    Entry::Node(node) => node.get(shift + BITS, hash, key),
+                }
+            }
+            Node::Collision { pairs, .. } => pairs
+                .iter()
+                .find(|(k, _)| k.borrow() == key)
+                .map(|(k, v)| (k, v)),
+        }
+    }
+
+    /// Returns whether a new entry was added, as opposed to an existing value being replaced.
+    fn insert(&mut self, shift: u32, hash: u64, key: K, value: V) -> bool {
+        match self {
+            Node::Branch { bitmap, entries } => {
+                let bit = bit(hash, shift);
+                let index = (*bitmap & (bit - 1)).count_ones() as usize;
+                if *bitmap & bit == 0 {
+                    entries.insert(index, Entry::Leaf { hash, key, value });
+                    *bitmap |= bit;
+                    return true;
+                }
+                let entry = &mut entries[index];
+                match entry {
+                    Entry::Node(node) => {
+                        return Rc::make_mut(node).insert(shift + BITS, hash, key, value)
+                    }
+                    Entry::Leaf {
+                        key: k, value: v, ..
+                    } if *k == key => {
+                        *v = value;
+                        return false;
+                    }
+                    Entry::Leaf { .. } => {}
+                }
+                // two different keys in the same place, so they move one level down
+                let placeholder = Entry::Node(Rc::new(Node::empty()));
+                if let Entry::Leaf {
+                    hash: old_hash,
+                    key: old_key,
+                    value: old_value,
+                } = mem::replace(entry, placeholder)
+                {
+                    *entry = Entry::Node(Rc::new(Node::pair(
+                        shift + BITS,
+                        (old_hash, old_key, old_value),
+                        (hash, key, value),
+                    )));
+                }
+                true
+            }
+            Node::Collision { hash: h, .. } if *h != hash => {
+                // the new key only shares a part of the hash, so the collision moves one level down
+                let bitmap = bit(*h, shift);
+                let collision = mem::replace(self, Node::empty());
+                *self = Node::Branch {
+                    bitmap,
+                    entries: vec![Entry::Node(Rc::new(collision))],
+                };
+                self.insert(shift, hash, key, value)
+            }
+            Node::Collision { pairs, .. } => match pairs.iter_mut().find(|(k, _)| *k == key) {
+                Some((_, v)) => {
+                    *v = value;
+                    false
+                }
+                None => {
+                    pairs.push((key, value));
+                    true
+                }
+            },
+        }
+    }
+
+    /// A node containing two entries with different keys.
+    fn pair(shift: u32, a: (u64, K, V), b: (u64, K, V)) -> Self {
+        if a.0 == b.0 {
+            return Node::Collision {
+                hash: a.0,
+                pairs: vec![(a.1, a.2), (b.1, b.2)],
+            };
+        }
+        let (bit_a, bit_b) = (bit(a.0, shift), bit(b.0, shift));
+        if bit_a == bit_b {
+            return Node::Branch {
+                bitmap: bit_a,
+                entries: vec![Entry::Node(Rc::new(Node::pair(shift + BITS, a, b)))],
+            };
+        }
+        let leaf = |(hash, key, value)| Entry::Leaf { hash, key, value };
+        let entries = if bit_a < bit_b {
+            vec![leaf(a), leaf(b)]
+        } else {
+            vec![leaf(b), leaf(a)]
+        };
+        Node::Branch {
+            bitmap: bit_a | bit_b,
+            entries,
+        }
+    }
+
+    /// Removes a key that must be present in the node.
+    fn remove<Q>(&mut self, shift: u32, hash: u64, key: &Q) -> V
+    where
+        K: Borrow<Q>,
+        Q: Eq + ?Sized,
+    {
+        match self {
+            Node::Branch { bitmap, entries } => {
+                let bit = bit(hash, shift);
+                let index = (*bitmap & (bit - 1)).count_ones() as usize;
+                if let Entry::Node(node) = &mut entries[index] {
+                    let node = Rc::make_mut(node);
+                    let value = node.remove(shift + BITS, hash, key);
+                    // a node with a single key is replaced by that key
+                    if let Some(leaf) = node.take_single_leaf() {
+                        entries[index] = leaf;
+                    }
+                    return value;
+                }
+                *bitmap &= !bit;
+                match entries.remove(index) {
+                    Entry::Leaf { value, .. } => value,
+                    Entry::Node(_) => unreachable!(),
+                }
+            }
+            Node::Collision { pairs, .. } => {
+                let index = pairs.iter().position(|(k, _)| k.borrow() == key).unwrap();
+                pairs.swap_remove(index).1
+            }
+        }
+    }
+
+    fn take_single_leaf(&mut self) -> Option<Entry<K, V>> {
+        match self {
+            Node::Branch { entries, .. }
+                if entries.len() == 1 && matches!(entries[0], Entry::Leaf { .. }) =>
+            {
+                entries.pop()
+            }
+            Node::Collision { hash, pairs } if pairs.len() == 1 => {
+                let (key, value) = pairs.pop().unwrap();
+                Some(Entry::Leaf {
+                    hash: *hash,
+                    key,
+                    value,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// An immutable hash map with cheap copies, `insert` and `remove`.
+#[derive(Clone)]
+pub struct PersistentHashMap<K, V> {
+    len: usize,
+    root: Rc<Node<K, V>>,
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> PersistentHashMap<K, V> {
+    pub fn new() -> Self {
+        Self {
+            len: 0,
+            root: Rc::new(Node::empty()),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn get_key_value<Q>(&self, key: &Q) -> Option<(&K, &V)>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.root.get(0, hash_of(key), key)
+    }
+
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.get_key_value(key).map(|(_, v)| v)
+    }
+
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.get_key_value(key).is_some()
+    }
+
+    pub fn insert(&mut self, key: K, value: V) {
+        let hash = hash_of(&key);
+        if Rc::make_mut(&mut self.root).insert(0, hash, key, value) {
+            self.len += 1;
+        }
+    }
+
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        // don't copy any nodes if there's nothing to remove
+        if !self.contains_key(key) {
+            return None;
+        }
+        self.len -= 1;
+        Some(Rc::make_mut(&mut self.root).remove(0, hash_of(key), key))
+    }
+
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter {
+            stack: vec![NodeIter::new(&self.root)],
+            remaining: self.len,
+        }
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &K> {
+        self.iter().map(|(k, _)| k)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.iter().map(|(_, v)| v)
+    }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> Default for PersistentHashMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> FromIterator<(K, V)> for PersistentHashMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = Self::new();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone> Extend<(K, V)> for PersistentHashMap<K, V> {
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
+        }
+    }
+}
+
+impl<K: Clone + Eq + Hash, V: Clone + PartialEq> PartialEq for PersistentHashMap<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len()
+            && self
+                .iter()
+                .all(|(k, v)| other.get(k).is_some_and(|other_v| v == other_v))
+    }
+}
+
+impl<K: Clone + Eq + Hash + fmt::Debug, V: Clone + fmt::Debug> fmt::Debug
+    for PersistentHashMap<K, V>
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+enum NodeIter<'a, K, V> {
+    Branch(std::slice::Iter<'a, Entry<K, V>>),
+    Collision(std::slice::Iter<'a, (K, V)>),
+}
+
+impl<'a, K, V> NodeIter<'a, K, V> {
+    fn new(node: &'a Node<K, V>) -> Self {
+        match node {
+            Node::Branch { entries, .. } => NodeIter::Branch(entries.iter()),
+            Node::Collision { pairs, .. } => NodeIter::Collision(pairs.iter()),
+        }
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    stack: Vec<NodeIter<'a, K, V>>,
+    remaining: usize,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        loop {
+            let next = match self.stack.last_mut()? {
+                NodeIter::Branch(entries) => match entries.next() {
+                    Some(Entry::Leaf { key, value, .. }) => Some((key, value)),
+                    Some(Entry::Node(node)) => {
+                        self.stack.push(NodeIter::new(node));
+                        continue;
+                    }
+                    None => None,
+                },
+                NodeIter::Collision(pairs) => pairs.next().map(|(k, v)| (k, v)),
+            };
+            match next {
+                Some(entry) => {
+                    self.remaining -= 1;
+                    return Some(entry);
+                }
+                None => {
+                    self.stack.pop();
+                }
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.remaining, Some(self.remaining))
+    }
+}
+
+impl<K, V> ExactSizeIterator for Iter<'_, K, V> {}
+
+impl<'a, K: Clone + Eq + Hash, V: Clone> IntoIterator for &'a PersistentHashMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A key with a bad hash function, to test collisions.
+    #[derive(Clone, PartialEq, Eq, Debug)]
+    struct Colliding(u32);
+
+    impl Hash for Colliding {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            (self.0 % 3).hash(state)
+        }
+    }
+
+    #[test]
+    fn insert_get_remove() {
+        let mut map = PersistentHashMap::new();
+        for i in 0..100_000 {
+            map.insert(i, i * 2);
+        }
+        map.insert(5, -1);
+        assert_eq!(map.len(), 100_000);
+        assert_eq!(map.get(&5), Some(&-1));
+        assert_eq!(map.get(&99_999), Some(&199_998));
+        assert_eq!(map.get(&100_000), None);
+        assert_eq!(map.iter().count(), 100_000);
+        let snapshot = map.clone();
+        for i in (0..100_000).step_by(2) {
+            assert!(map.remove(&i).is_some());
+        }
+        assert_eq!(map.remove(&0), None);
+        assert_eq!(map.len(), 50_000);
+        assert!(map.keys().all(|k| k % 2 == 1));
+        assert_eq!(snapshot.len(), 100_000);
+        assert_eq!(snapshot.get(&0), Some(&0));
+    }
+
+    #[test]
+    fn collisions() {
+        let mut map = PersistentHashMap::new();
+        for i in 0..30 {
+            map.insert(Colliding(i), i);
+        }
+        assert_eq!(map.len(), 30);
+        assert!((0..30).all(|i| map.get(&Colliding(i)) == Some(&i)));
+        for i in 0..29 {
+            assert_eq!(map.remove(&Colliding(i)), Some(i));
+        }
+        assert_eq!(map.iter().collect::<Vec<_>>(), [(&Colliding(29), &29)]);
+    }
+}
@@ -0,0 +1,124 @@
+//! A persistent hash set, which is a `PersistentHashMap` without values.
+
+use std::{borrow::Borrow, fmt, hash::Hash, iter::FromIterator};
+
+use crate::hash_map::{self, PersistentHashMap};
+
+#[derive(Clone)]
+pub struct PersistentHashSet<T> {
+    map: PersistentHashMap<T, ()>,
+}
+
+impl<T: Clone + Eq + Hash> PersistentHashSet<T> {
+    pub fn new() -> Self {
+        Self {
+            map: PersistentHashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn contains<Q>(&self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.contains_key(value)
+    }
+
+    /// The element of the set that is equal to `value`.
+    pub fn get<Q>(&self, value: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.get_key_value(value).map(|(k, _)| k)
+    }
+
+    /// Returns whether the value was newly inserted.
+    pub fn insert(&mut self, value: T) -> bool {
+        let len = self.len();
+        self.map.insert(value, ());
+        self.len() != len
+    }
+
+    /// Returns whether the value was present.
+    pub fn remove<Q>(&mut self, value: &Q) -> bool
+    where
+        T: Borrow<Q>,
+        Q: Eq + Hash + ?Sized,
+    {
+        self.map.remove(value).is_some()
+    }
+
+    pub fn is_subset(&self, other: &Self) -> bool {
+        self.len() <= other.len() && self.iter().all(|v| other.contains(v))
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter(self.map.iter())
+    }
+}
+
+impl<T: Clone + Eq + Hash> Default for PersistentHashSet<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone + Eq + Hash> FromIterator<T> for PersistentHashSet<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut set = Self::new();
+        set.extend(iter);
+        set
+    }
+}
+
+impl<T: Clone + Eq + Hash> Extend<T> for PersistentHashSet<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        self.map.extend(iter.into_iter().map(|v| (v, ())));
+    }
+}
+
+impl<T: Clone + Eq + Hash> PartialEq for PersistentHashSet<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.map == other.map
+    }
+}
+
+impl<T: Clone + Eq + Hash + fmt::Debug> fmt::Debug for PersistentHashSet<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.iter()).finish()
+    }
+}
+
+pub struct Iter<'a, T>(hash_map::Iter<'a, T, ()>);
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        self.0.next().map(|(v, _)| v)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        self.0.size_hint()
+    }
+}
+
+impl<T> ExactSizeIterator for Iter<'_, T> {}
+
+impl<'a, T: Clone + Eq + Hash> IntoIterator for &'a PersistentHashSet<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
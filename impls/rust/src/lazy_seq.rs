@@ -0,0 +1,232 @@
+//! Lazy sequences, whose elements are computed when they are first needed.
+//!
+//! A lazy sequence wraps a thunk that returns a seqable value, like nil, a collection, a
+//! string or another lazy sequence. Once realized, it's either empty or a first element followed by
+//! the rest, which is usually another lazy sequence. The result is memoised, so the thunk
+//! runs at most once.
+
+use std::{cell::RefCell, fmt, mem, rc::Rc};
+
+use crate::{
+    runtime_errors::{self, RuntimeResult},
+    value::{SeqIter, Value},
+};
+
+type Thunk = Box<dyn FnOnce() -> RuntimeResult<Value>>;
+
+/// A realized sequence: `None` if it's empty, otherwise the first element and the rest.
+pub type Step = Option<(Value, Value)>;
+
+enum State {
+    Pending(Thunk),
+    /// The thunk is running. Realizing the sequence again from inside it is an error.
+    Running,
+    /// The result of the thunk, which may still be another lazy sequence.
+    Evaluated(RuntimeResult<Value>),
+    Realized(Step),
+}
+
+pub struct LazySeq {
+    state: RefCell<State>,
+}
+
+impl LazySeq {
+    pub fn new(thunk: impl FnOnce() -> RuntimeResult<Value> + 'static) -> Self {
+        Self {
+            state: RefCell::new(State::Pending(Box::new(thunk))),
+        }
+    }
+
+    /// A sequence that is already realized, like the result of `cons`.
+    pub fn cons(first: Value, rest: Value) -> Self {
+        Self {
+            state: RefCell::new(State::Realized(Some((first, rest)))),
+        }
+    }
+
+    pub fn is_realized(&self) -> bool {
+        matches!(&*self.state.borrow(), State::Realized(_))
+    }
+
+    /// Realizes the sequence and returns its first element and the rest.
+    pub fn step(&self) -> RuntimeResult<Step> {
+        let mut value = match self.evaluate()? {
+            Ok(value) => value,
+            Err(step) => return Ok(step),
+        };
+        // when the thunk returns another lazy sequence, realize that one iteratively,
+        // so that long chains (like a `filter` written in mal) don't overflow the stack
+        let step = loop {
+            value = match value {
+                Value::LazySeq(inner, _) => match inner.evaluate()? {
+                    Ok(value) => value,
+                    Err(step) => break step,
+                },
+                Value::Nil => break None,
+                v => {
+                    let mut elements = SeqIter::new(v)?;
+                    let first = elements.next().transpose()?;
+                    break first.map(|first| (first, elements.into_seq()));
+                }
+            }
+        };
+        *self.state.borrow_mut() = State::Realized(step.clone());
+        Ok(step)
+    }
+
+    /// Runs the thunk if necessary. Returns its result, or the step if already realized.
+    fn evaluate(&self) -> RuntimeResult<Result<Value, Step>> {
+        let state = mem::replace(&mut *self.state.borrow_mut(), State::Running);
+        let result = match state {
+            State::Pending(thunk) => thunk(),
+            State::Running => {
+                return Err(runtime_errors::error_to_string(
+                    "lazy sequence depends on itself",
+                ))
+            }
+            State::Evaluated(result) => result,
+            State::Realized(step) => {
+                *self.state.borrow_mut() = State::Realized(step.clone());
+                return Ok(Err(step));
+            }
+        };
+        *self.state.borrow_mut() = State::Evaluated(result.clone());
+        result.map(Ok)
+    }
+
+    /// Takes the value this sequence links to, so that dropping it doesn't recurse.
+    fn take_next(&mut self) -> Value {
+        match self.state.get_mut() {
+            State::Realized(Some((_, rest))) => mem::replace(rest, Value::Nil),
+            State::Evaluated(Ok(value)) => mem::replace(value, Value::Nil),
+            _ => Value::Nil,
+        }
+    }
+}
+
+impl Drop for LazySeq {
+    fn drop(&mut self) {
+        // drop long chains of realized sequences iteratively
+        let mut next = self.take_next();
+        while let Value::LazySeq(seq, _) = next {
+            next = match Rc::try_unwrap(seq) {
+                Ok(mut seq) => seq.take_next(),
+                Err(_) => break,
+            };
+        }
+    }
+}
+
+impl fmt::Debug for LazySeq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match &*self.state.borrow() {
+            State::Pending(_) | State::Running => "pending",
+            State::Evaluated(_) => "evaluated",
+            State::Realized(_) => "realized",
+        };
+        write!(f, "LazySeq({})", state)
+    }
+}
+
+/// A lazy sequence value from a thunk.
+pub fn lazy(thunk: impl FnOnce() -> RuntimeResult<Value> + 'static) -> Value {
+    Value::LazySeq(Rc::new(LazySeq::new(thunk)), Box::new(Value::Nil))
+}
+
+/// A realized lazy sequence value, which doesn't realize `rest`.
+pub fn cons(first: Value, rest: Value) -> Value {
+    Value::LazySeq(Rc::new(LazySeq::cons(first, rest)), Box::new(Value::Nil))
+}
+
+/// Realizes all the elements of a lazy sequence.
+pub fn realized_elements(seq: &Rc<LazySeq>) -> RuntimeResult<Vec<Value>> {
+    SeqIter::Seq(Value::LazySeq(seq.clone(), Box::new(Value::Nil))).collect()
+}
+
+/// The elements of a lazy sequence that are already realized, at most `limit` of them,
+/// without running any thunks. The flag is set if the sequence may have more elements.
+pub fn realized_prefix(seq: &Rc<LazySeq>, limit: usize) -> (Vec<Value>, bool) {
+    let mut elements = Vec::new();
+    let mut next = Value::LazySeq(seq.clone(), Box::new(Value::Nil));
+    loop {
+        next = match next {
+            Value::LazySeq(seq, _) => match &*seq.state.borrow() {
+                State::Realized(None) => return (elements, false),
+                State::Realized(Some((first, rest))) if elements.len() < limit => {
+                    elements.push(first.clone());
+                    rest.clone()
+                }
+                _ => return (elements, true),
+            },
+            Value::List(l, _) => return take_up_to(elements, l.iter(), limit),
+            Value::Vec(v, _) => return take_up_to(elements, v.iter(), limit),
+            _ => return (elements, false),
+        }
+    }
+}
+
+fn take_up_to<'a>(
+    mut elements: Vec<Value>,
+    rest: impl ExactSizeIterator<Item = &'a Value>,
+    limit: usize,
+) -> (Vec<Value>, bool) {
+    let more = rest.len() > limit - elements.len();
+    elements.extend(rest.take(limit - elements.len()).cloned());
+    (elements, more)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::number::Int;
+
+    fn naturals(from: i64) -> Value {
+        lazy(move || Ok(cons(Value::Number(Int::Small(from)), naturals(from + 1))))
+    }
+
+    #[test]
+    fn memoised() {
+        let calls = Rc::new(RefCell::new(0));
+        let counter = calls.clone();
+        let seq = lazy(move || {
+            *counter.borrow_mut() += 1;
+            Ok(Value::List(vec![Value::Nil], Box::new(Value::Nil)))
+        });
+        for _ in 0..2 {
+            assert_eq!(SeqIter::new(seq.clone()).unwrap().count(), 1);
+        }
+        assert_eq!(*calls.borrow(), 1);
+    }
+
+    #[test]
+    fn infinite_and_long_chains() {
+        let mut iter = SeqIter::new(naturals(0)).unwrap();
+        let last = iter.by_ref().take(200_000).last().unwrap().unwrap();
+        assert!(matches!(last, Value::Number(Int::Small(199_999))));
+        // a realized chain that is dropped at once
+        let seq = naturals(0);
+        assert_eq!(
+            SeqIter::new(seq.clone()).unwrap().take(200_000).count(),
+            200_000
+        );
+        drop(seq);
+        // a lazy sequence whose thunk returns a lazy sequence, many times over
+        let nested = (0..200_000).fold(Value::Nil, |seq, _| lazy(move || Ok(seq)));
+        assert_eq!(SeqIter::new(nested).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn depends_on_itself() {
+        let cell = Rc::new(RefCell::new(Value::Nil));
+        let inner = cell.clone();
+        let seq = lazy(move || {
+            let seq = inner.borrow().clone();
+            SeqIter::new(seq)?.next().transpose()?;
+            Ok(Value::Nil)
+        });
+        *cell.borrow_mut() = seq.clone();
+        assert!(SeqIter::new(seq).unwrap().next().unwrap().is_err());
+        // break the cycle
+        *cell.borrow_mut() = Value::Nil;
+    }
+}
@@ -1,38 +1,41 @@
-use std::{cell::RefCell, convert::TryInto, rc::Rc};
-
-use rustc_hash::FxHashMap;
+use std::{cell::RefCell, ops::RangeBounds, rc::Rc};
 
 use crate::{
     env::Env,
     eval_fn_no_tco,
+    hash_map::PersistentHashMap,
+    lazy_seq,
+    number::Int,
     printer::pr_str,
     reader::{read_str, ParseError},
     runtime_errors::{self, error_to_string_with_ctx, RuntimeResult},
-    value::{HostFn, MalFnPtr, Value},
+    value::{HostFn, MalFnPtr, SeqIter, Value},
 };
 
+mod compare;
+mod lazy;
+mod numeric;
+mod pattern;
+mod seq;
+mod set;
+mod sorted;
+mod string;
+
+type Args = std::vec::IntoIter<Value>;
+
+fn make_fn_val(f: fn(Args, Rc<RefCell<Env>>) -> RuntimeResult<Value>) -> Value {
+    Value::HostFn(HostFn::ByPtr(MalFnPtr(f)), Box::new(Value::Nil))
+}
+
 pub fn init_env(env: &mut Env) {
-    fn make_fn_val(
-        f: fn(std::vec::IntoIter<Value>, Rc<RefCell<Env>>) -> RuntimeResult<Value>,
-    ) -> Value {
-        Value::HostFn(HostFn::ByPtr(MalFnPtr(f)), Box::new(Value::Nil))
-    }
-    env.set(
-        "+",
-        make_fn_val(|mut args, _| Ok(args.next().unwrap() + args.next().unwrap())),
-    );
-    env.set(
-        "-",
-        make_fn_val(|mut args, _| Ok(args.next().unwrap() - args.next().unwrap())),
-    );
-    env.set(
-        "*",
-        make_fn_val(|mut args, _| Ok(args.next().unwrap() * args.next().unwrap())),
-    );
-    env.set(
-        "/",
-        make_fn_val(|mut args, _| args.next().unwrap() / args.next().unwrap()),
-    );
+    numeric::init_env(env);
+    compare::init_env(env);
+    string::init_env(env);
+    pattern::init_env(env);
+    set::init_env(env);
+    sorted::init_env(env);
+    lazy::init_env(env);
+    seq::init_env(env);
     env.set(
         "pr-str",
         make_fn_val(|args, _| {
@@ -41,6 +44,7 @@ pub fn init_env(env: &mut Env) {
                 if i != 0 {
                     string.push(' ');
                 }
+                item.realize()?;
                 pr_str(&item, &mut string, true).unwrap();
             }
             Ok(Value::String(string))
@@ -51,6 +55,7 @@ pub fn init_env(env: &mut Env) {
         make_fn_val(|args, _| {
             let mut string = String::new();
             for item in args {
+                item.realize()?;
                 pr_str(&item, &mut string, false).unwrap();
             }
             Ok(Value::String(string))
@@ -64,6 +69,7 @@ pub fn init_env(env: &mut Env) {
                 if i != 0 {
                     string.push(' ');
                 }
+                item.realize()?;
                 pr_str(&item, &mut string, true).unwrap();
             }
             println!("{}", string);
@@ -78,6 +84,7 @@ pub fn init_env(env: &mut Env) {
                 if i != 0 {
                     string.push(' ');
                 }
+                item.realize()?;
                 pr_str(&item, &mut string, false).unwrap();
             }
             println!("{}", string);
@@ -91,6 +98,7 @@ pub fn init_env(env: &mut Env) {
     env.set(
         "list?",
         make_fn_val(|mut args, _| {
+            ensure_arity(&args, "list?", 1..=1)?;
             Ok(Value::Bool(matches!(
                 args.next().unwrap(),
                 Value::List(_, _)
@@ -100,61 +108,43 @@ pub fn init_env(env: &mut Env) {
     env.set(
         "empty?",
         make_fn_val(|mut args, _| {
-            Ok(Value::Bool(
-                args.next()
-                    .unwrap()
-                    .try_as_list_or_vec()
-                    .map(|l| l.is_empty())
-                    .unwrap_or(true),
-            ))
+            ensure_arity(&args, "empty?", 1..=1)?;
+            Ok(Value::Bool(args.next().unwrap().seq_is_empty()?))
         }),
     );
     env.set(
         "count",
         make_fn_val(|mut args, _| {
-            Ok(Value::Number(
-                args.next()
-                    .unwrap()
-                    .try_as_list_or_vec()
-                    .map(|l| l.len())
-                    .unwrap_or(0) as i32,
-            ))
+            ensure_arity(&args, "count", 1..=1)?;
+            Ok(Value::Number(args.next().unwrap().seq_count()?.into()))
         }),
     );
     env.set(
         "=",
-        make_fn_val(|mut args, _| Ok(Value::Bool(args.next().unwrap() == args.next().unwrap()))),
-    );
-    env.set(
-        "<",
-        make_fn_val(|mut args, _| Ok(Value::Bool(args.next().unwrap() < args.next().unwrap()))),
-    );
-    env.set(
-        ">",
-        make_fn_val(|mut args, _| Ok(Value::Bool(args.next().unwrap() > args.next().unwrap()))),
-    );
-    env.set(
-        "<=",
-        make_fn_val(|mut args, _| Ok(Value::Bool(args.next().unwrap() <= args.next().unwrap()))),
-    );
-    env.set(
-        ">=",
-        make_fn_val(|mut args, _| Ok(Value::Bool(args.next().unwrap() >= args.next().unwrap()))),
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "=", 2..=2)?;
+            let (a, b) = (args.next().unwrap(), args.next().unwrap());
+            a.realize()?;
+            b.realize()?;
+            Ok(Value::Bool(a == b))
+        }),
     );
 
     env.set(
         "read-string",
-        make_fn_val(
-            |mut args, _| match read_str(args.next().unwrap().try_as_str()?) {
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "read-string", 1..=1)?;
+            match read_str(args.next().unwrap().try_as_str()?) {
                 Ok(v) => Ok(v),
                 Err(ParseError::EmptyInput) => Ok(Value::Nil),
                 Err(e) => Err(error_to_string_with_ctx("parsing failed", e)),
-            },
-        ),
+            }
+        }),
     );
     env.set(
         "slurp",
         make_fn_val(|mut args, _| {
+            ensure_arity(&args, "slurp", 1..=1)?;
             let file = args.next().unwrap().try_into_string()?;
             std::fs::read_to_string(&file)
                 .map(Value::String)
@@ -169,60 +159,90 @@ pub fn init_env(env: &mut Env) {
 
     env.set(
         "atom",
-        make_fn_val(|mut args, _| Ok(Value::Atom(Rc::new(RefCell::new(args.next().unwrap()))))),
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "atom", 1..=1)?;
+            Ok(Value::Atom(Rc::new(RefCell::new(args.next().unwrap()))))
+        }),
     );
     env.set(
         "atom?",
-        make_fn_val(|mut args, _| Ok(Value::Bool(matches!(args.next().unwrap(), Value::Atom(_))))),
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "atom?", 1..=1)?;
+            Ok(Value::Bool(matches!(args.next().unwrap(), Value::Atom(_))))
+        }),
     );
     env.set(
         "deref",
-        make_fn_val(|mut args, _| match &args.next().unwrap() {
-            Value::Atom(v) => Ok(v.borrow().clone()),
-            v => Err(runtime_errors::not_a("atom", v)),
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "deref", 1..=1)?;
+            match &args.next().unwrap() {
+                Value::Atom(v) => Ok(v.borrow().clone()),
+                v => Err(runtime_errors::not_a("atom", v)),
+            }
         }),
     );
     env.set(
         "reset!",
-        make_fn_val(|mut args, _| match &args.next().unwrap() {
-            Value::Atom(v) => {
-                let arg = args.next().unwrap();
-                v.replace(arg.clone());
-                Ok(arg)
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "reset!", 2..=2)?;
+            match &args.next().unwrap() {
+                Value::Atom(v) => {
+                    let arg = args.next().unwrap();
+                    v.replace(arg.clone());
+                    Ok(arg)
+                }
+                v => Err(runtime_errors::not_a("atom", v)),
             }
-            v => Err(runtime_errors::not_a("atom", v)),
         }),
     );
     env.set(
         "swap!",
-        make_fn_val(|mut args, env| match args.next().unwrap() {
-            Value::Atom(v) => {
-                let mut fn_args = vec![v.borrow().clone()];
-                let fun = args.next().unwrap();
-                fn_args.extend(args);
-                let result = eval_fn_no_tco(fun, fn_args, env)?;
-                v.replace(result.clone());
-                Ok(result)
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "swap!", 2..)?;
+            match args.next().unwrap() {
+                Value::Atom(v) => {
+                    let mut fn_args = vec![v.borrow().clone()];
+                    let fun = args.next().unwrap();
+                    fn_args.extend(args);
+                    let result = eval_fn_no_tco(fun, fn_args, env)?;
+                    v.replace(result.clone());
+                    Ok(result)
+                }
+                v => Err(runtime_errors::not_a("atom", &v)),
             }
-            v => Err(runtime_errors::not_a("atom", &v)),
         }),
     );
 
     env.set(
         "cons",
         make_fn_val(|mut args, _| {
-            let mut list = Vec::with_capacity(args.len() + 1);
-            list.push(args.next().unwrap());
-            list.append(&mut args.next().unwrap().try_into_list_or_vec()?);
-            Ok(Value::List(list, Box::new(Value::Nil)))
+            ensure_arity(&args, "cons", 2..=2)?;
+            let first = args.next().unwrap();
+            match args.next().unwrap() {
+                // don't realize the rest, so that lazy sequences can be built with `cons`
+                rest @ Value::LazySeq(_, _) => Ok(lazy_seq::cons(first, rest)),
+                rest => {
+                    let mut list = vec![first];
+                    list.append(&mut rest.seq_elements()?);
+                    Ok(Value::List(list, Box::new(Value::Nil)))
+                }
+            }
         }),
     );
     env.set(
         "concat",
         make_fn_val(|args, _| {
+            if args
+                .as_slice()
+                .iter()
+                .any(|arg| matches!(arg, Value::LazySeq(_, _)))
+            {
+                let colls = SeqIter::new(Value::List(args.collect(), Box::new(Value::Nil)))?;
+                return Ok(lazy::concat(SeqIter::new(Value::Nil)?, colls));
+            }
             let mut list = Vec::new();
             for arg in args {
-                list.append(&mut arg.try_into_list_or_vec()?);
+                list.append(&mut arg.seq_elements()?);
             }
             Ok(Value::List(list, Box::new(Value::Nil)))
         }),
@@ -231,79 +251,62 @@ pub fn init_env(env: &mut Env) {
     env.set(
         "vec",
         make_fn_val(|mut args, _| {
-            Ok(Value::Vec(
-                args.next().unwrap().try_into_list_or_vec()?,
-                Box::new(Value::Nil),
-            ))
+            ensure_arity(&args, "vec", 1..=1)?;
+            match args.next().unwrap() {
+                Value::Vec(v, _) => Ok(Value::Vec(v, Box::new(Value::Nil))),
+                coll => Ok(Value::Vec(
+                    coll.seq_elements()?.into(),
+                    Box::new(Value::Nil),
+                )),
+            }
         }),
     );
 
     env.set(
         "nth",
-        make_fn_val(|mut args, _| match args.next().unwrap() {
-            Value::List(mut l, _) | Value::Vec(mut l, _) => {
-                let index_unconverted: i32 = args.next().unwrap().try_as_number().unwrap();
-                let index: usize = index_unconverted
-                    .try_into()
-                    .map_err(|_| runtime_errors::out_of_bounds(l.len(), index_unconverted))?;
-
-                if index >= l.len() {
-                    Err(runtime_errors::out_of_bounds(l.len(), index_unconverted))
-                } else {
-                    Ok(l.swap_remove(index))
-                }
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "nth", 2..=2)?;
+            match args.next().unwrap() {
+                Value::Nil => Ok(Value::Nil),
+                coll => coll.seq_nth(&args.next().unwrap().try_into_int()?),
             }
-            Value::Nil => Ok(Value::Nil),
-            v => Err(runtime_errors::not_a("list", &v)),
         }),
     );
     env.set(
         "first",
-        make_fn_val(|mut args, _| match args.next().unwrap() {
-            Value::List(mut l, _) | Value::Vec(mut l, _) if !l.is_empty() => Ok(l.swap_remove(0)),
-            Value::List(_, _) | Value::Vec(_, _) | Value::Nil => Ok(Value::Nil),
-            v => Err(runtime_errors::not_a("list", &v)),
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "first", 1..=1)?;
+            args.next().unwrap().seq_first()
         }),
     );
     env.set(
         "rest",
-        make_fn_val(|mut args, _| match args.next().unwrap() {
-            Value::List(mut l, _) | Value::Vec(mut l, _) if !l.is_empty() => {
-                l.remove(0);
-                Ok(Value::List(l, Box::new(Value::Nil)))
-            }
-            Value::List(_, _) | Value::Vec(_, _) | Value::Nil => {
-                Ok(Value::List(Vec::new(), Box::new(Value::Nil)))
-            }
-            v => Err(runtime_errors::not_a("list", &v)),
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "rest", 1..=1)?;
+            args.next().unwrap().seq_rest()
         }),
     );
     env.set(
         "throw",
-        make_fn_val(|mut args, _| Err(args.next().unwrap())),
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "throw", 1..=1)?;
+            Err(args.next().unwrap())
+        }),
     );
 
     env.set("apply", Value::HostFn(HostFn::Apply, Box::new(Value::Nil)));
-    env.set(
-        "map",
-        make_fn_val(|mut args, env| {
-            let function = args.next().unwrap();
-            let list = args.next().unwrap().try_into_list_or_vec()?;
-            let mut new_list = Vec::with_capacity(list.len());
-            for e in list.into_iter() {
-                new_list.push(eval_fn_no_tco(function.clone(), vec![e], env.clone())?);
-            }
-            Ok(Value::List(new_list, Box::new(Value::Nil)))
-        }),
-    );
 
     env.set(
         "nil?",
-        make_fn_val(|mut args, _| Ok(Value::Bool(matches!(args.next().unwrap(), Value::Nil)))),
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "nil?", 1..=1)?;
+            Ok(Value::Bool(matches!(args.next().unwrap(), Value::Nil)))
+        }),
     );
     env.set(
         "true?",
         make_fn_val(|mut args, _| {
+            ensure_arity(&args, "true?", 1..=1)?;
             Ok(Value::Bool(matches!(
                 args.next().unwrap(),
                 Value::Bool(true)
@@ -313,6 +316,7 @@ pub fn init_env(env: &mut Env) {
     env.set(
         "false?",
         make_fn_val(|mut args, _| {
+            ensure_arity(&args, "false?", 1..=1)?;
             Ok(Value::Bool(matches!(
                 args.next().unwrap(),
                 Value::Bool(false)
@@ -322,6 +326,7 @@ pub fn init_env(env: &mut Env) {
     env.set(
         "symbol?",
         make_fn_val(|mut args, _| {
+            ensure_arity(&args, "symbol?", 1..=1)?;
             Ok(Value::Bool(matches!(
                 args.next().unwrap(),
                 Value::Symbol(_)
@@ -331,26 +336,25 @@ pub fn init_env(env: &mut Env) {
     env.set(
         "symbol",
         make_fn_val(|mut args, _| {
-            Ok(Value::Symbol(
-                args.next().unwrap().try_as_str()?.to_string(),
-            ))
+            ensure_arity(&args, "symbol", 1..=1)?;
+            Ok(Value::Symbol(args.next().unwrap().try_as_str()?.into()))
         }),
     );
     env.set(
         "keyword",
-        make_fn_val(|mut args, _| match args.next().unwrap() {
-            Value::String(s) => Ok(Value::Keyword(format!(
-                "{}:{}",
-                char::from_u32(0x29E).unwrap(),
-                s
-            ))),
-            v @ Value::Keyword(_) => Ok(v),
-            v => Err(runtime_errors::not_a("string or keyword", &v)),
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "keyword", 1..=1)?;
+            match args.next().unwrap() {
+                Value::String(s) => Ok(Value::Keyword(format!(":{}", s).into())),
+                v @ Value::Keyword(_) => Ok(v),
+                v => Err(runtime_errors::not_a("string or keyword", &v)),
+            }
         }),
     );
     env.set(
         "keyword?",
         make_fn_val(|mut args, _| {
+            ensure_arity(&args, "keyword?", 1..=1)?;
             Ok(Value::Bool(matches!(
                 args.next().unwrap(),
                 Value::Keyword(_)
@@ -364,6 +368,7 @@ pub fn init_env(env: &mut Env) {
     env.set(
         "vector?",
         make_fn_val(|mut args, _| {
+            ensure_arity(&args, "vector?", 1..=1)?;
             Ok(Value::Bool(matches!(
                 args.next().unwrap(),
                 Value::Vec(_, _)
@@ -373,19 +378,20 @@ pub fn init_env(env: &mut Env) {
     env.set(
         "sequential?",
         make_fn_val(|mut args, _| {
+            ensure_arity(&args, "sequential?", 1..=1)?;
             Ok(Value::Bool(matches!(
                 args.next().unwrap(),
-                Value::Vec(_, _) | Value::List(_, _)
+                Value::Vec(_, _) | Value::List(_, _) | Value::LazySeq(_, _)
             )))
         }),
     );
     env.set(
         "hash-map",
         make_fn_val(|mut args, _| {
-            let mut map = FxHashMap::default();
+            let mut map = PersistentHashMap::new();
             ensure_even_args(&args)?;
             while let Some(v) = args.next() {
-                map.insert(v.as_hash_map_key()?.to_owned(), args.next().unwrap());
+                map.insert(v, args.next().unwrap());
             }
             Ok(Value::Map(map, Box::new(Value::Nil)))
         }),
@@ -393,72 +399,199 @@ pub fn init_env(env: &mut Env) {
     env.set(
         "map?",
         make_fn_val(|mut args, _| {
+            ensure_arity(&args, "map?", 1..=1)?;
             Ok(Value::Bool(matches!(
                 args.next().unwrap(),
-                Value::Map(_, _)
+                Value::Map(_, _) | Value::SortedMap(_, _)
             )))
         }),
     );
     env.set(
         "assoc",
-        make_fn_val(|mut args, _| {
-            let mut map = args.next().unwrap().try_into_map()?;
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "assoc", 1..)?;
+            let mut coll = args.next().unwrap();
             ensure_even_args(&args)?;
-            while let Some(v) = args.next() {
-                map.insert(v.as_hash_map_key()?.to_owned(), args.next().unwrap());
+            while let Some(k) = args.next() {
+                coll = assoc(coll, k, args.next().unwrap(), &env)?;
             }
-            Ok(Value::Map(map, Box::new(Value::Nil)))
+            Ok(coll)
         }),
     );
     env.set(
         "dissoc",
-        make_fn_val(|mut args, _| {
-            let mut map = args.next().unwrap().try_into_map()?;
-            for arg in args.skip(1) {
-                map.remove(arg.as_hash_map_key()?);
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "dissoc", 1..)?;
+            match args.next().unwrap() {
+                // like in Clojure, nil is an empty map
+                Value::Nil => Ok(Value::Nil),
+                Value::SortedMap(mut map, _) => {
+                    for arg in args {
+                        map.remove(&arg, &env)?;
+                    }
+                    Ok(Value::SortedMap(map, Box::new(Value::Nil)))
+                }
+                map => {
+                    let mut map = map.try_into_map()?;
+                    for arg in args {
+                        map.remove(&arg);
+                    }
+                    Ok(Value::Map(map, Box::new(Value::Nil)))
+                }
             }
-            Ok(Value::Map(map, Box::new(Value::Nil)))
         }),
     );
     env.set(
         "get",
-        make_fn_val(|mut args, _| {
-            // TODO: should we just consider Nil to be an empty map in try_as map?
-            let map = args.next().unwrap();
-            if matches!(&map, Value::Nil) {
-                return Ok(Value::Nil);
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "get", 2..=3)?;
+            let coll = args.next().unwrap();
+            let key = args.next().unwrap();
+            Ok(get(&coll, &key, &env)?.unwrap_or_else(|| args.next().unwrap_or(Value::Nil)))
+        }),
+    );
+    env.set(
+        "get-in",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "get-in", 2..=3)?;
+            let mut coll = args.next().unwrap();
+            for key in args.next().unwrap().seq_elements()? {
+                match get(&coll, &key, &env)? {
+                    Some(v) => coll = v,
+                    None => return Ok(args.next().unwrap_or(Value::Nil)),
+                }
+            }
+            Ok(coll)
+        }),
+    );
+    env.set(
+        "assoc-in",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "assoc-in", 3..=3)?;
+            let coll = args.next().unwrap();
+            let keys = args.next().unwrap().seq_elements()?;
+            let value = args.next().unwrap();
+            update_in(coll, &keys, &env, &mut |_| Ok(value.clone()))
+        }),
+    );
+    env.set(
+        "update",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "update", 3..)?;
+            let coll = args.next().unwrap();
+            let key = args.next().unwrap();
+            let f = args.next().unwrap();
+            let old = get(&coll, &key, &env)?.unwrap_or(Value::Nil);
+            let new = eval_fn_no_tco(f, std::iter::once(old).chain(args).collect(), env.clone())?;
+            assoc(coll, key, new, &env)
+        }),
+    );
+    env.set(
+        "update-in",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "update-in", 3..)?;
+            let coll = args.next().unwrap();
+            let keys = args.next().unwrap().seq_elements()?;
+            let f = args.next().unwrap();
+            let extra_args: Vec<_> = args.collect();
+            update_in(coll, &keys, &env, &mut |old| {
+                let fn_args = std::iter::once(old).chain(extra_args.iter().cloned());
+                eval_fn_no_tco(f.clone(), fn_args.collect(), env.clone())
+            })
+        }),
+    );
+    env.set(
+        "merge",
+        make_fn_val(|args, env| {
+            // like in Clojure, nil arguments are skipped, and merging only nils gives nil
+            let mut maps = args.filter(|map| !matches!(map, Value::Nil));
+            match maps.next() {
+                Some(first) => conj(first, maps.collect::<Vec<_>>().into_iter(), &env),
+                None => Ok(Value::Nil),
+            }
+        }),
+    );
+    env.set(
+        "merge-with",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "merge-with", 1..)?;
+            let f = args.next().unwrap();
+            let mut maps = args.filter(|map| !matches!(map, Value::Nil));
+            let mut merged = match maps.next() {
+                Some(first) => first,
+                None => return Ok(Value::Nil),
+            };
+            for map in maps {
+                for (k, v) in map_entries(map)? {
+                    let v = match get(&merged, &k, &env)? {
+                        Some(old) => eval_fn_no_tco(f.clone(), vec![old, v], env.clone())?,
+                        None => v,
+                    };
+                    merged = assoc(merged, k, v, &env)?;
+                }
             }
-            let mut map = map.try_into_map()?;
-            // We can remove from the map because the map is not used anywhere else.
-            // If we ever switch to refcounting values or even GC this would not likely be the best solution.
-            Ok(map
-                .remove(args.next().unwrap().as_hash_map_key()?)
-                .unwrap_or(Value::Nil))
+            Ok(merged)
+        }),
+    );
+    env.set(
+        "select-keys",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "select-keys", 2..=2)?;
+            let coll = args.next().unwrap();
+            let mut selected = PersistentHashMap::new();
+            for key in args.next().unwrap().seq_elements()? {
+                if let Some(v) = get(&coll, &key, &env)? {
+                    selected.insert(key, v);
+                }
+            }
+            Ok(Value::Map(selected, Box::new(Value::Nil)))
+        }),
+    );
+    env.set(
+        "find",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "find", 2..=2)?;
+            let coll = args.next().unwrap();
+            let key = args.next().unwrap();
+            Ok(match get(&coll, &key, &env)? {
+                Some(v) => Value::map_entry(&key, &v),
+                None => Value::Nil,
+            })
         }),
     );
     env.set(
         "contains?",
-        make_fn_val(|mut args, _| {
-            let map = args.next().unwrap().try_into_map()?;
-            Ok(Value::Bool(
-                map.contains_key(args.next().unwrap().as_hash_map_key()?),
-            ))
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "contains?", 2..=2)?;
+            match args.next().unwrap() {
+                Value::Set(set, _) => Ok(Value::Bool(set.contains(&args.next().unwrap()))),
+                Value::SortedMap(map, _) => {
+                    Ok(Value::Bool(map.get(&args.next().unwrap(), &env)?.is_some()))
+                }
+                Value::SortedSet(set, _) => {
+                    Ok(Value::Bool(set.get(&args.next().unwrap(), &env)?.is_some()))
+                }
+                map => {
+                    let map = map.try_into_map()?;
+                    Ok(Value::Bool(map.contains_key(&args.next().unwrap())))
+                }
+            }
         }),
     );
     env.set(
         "keys",
         make_fn_val(|mut args, _| {
-            let map = args.next().unwrap().try_into_map()?;
+            ensure_arity(&args, "keys", 1..=1)?;
+            let map = args.next().unwrap();
+            if let Value::SortedMap(map, _) = map {
+                return Ok(Value::List(
+                    map.entries.iter().map(|(k, _)| k.clone()).collect(),
+                    Box::new(Value::Nil),
+                ));
+            }
+            let map = map.try_into_map()?;
             Ok(Value::List(
-                map.into_keys()
-                    .map(|v| {
-                        if v.starts_with(char::from_u32(0x29E).unwrap()) {
-                            Value::Keyword(v)
-                        } else {
-                            Value::String(v)
-                        }
-                    })
-                    .collect(),
+                map.keys().cloned().collect(),
                 Box::new(Value::Nil),
             ))
         }),
@@ -466,9 +599,17 @@ pub fn init_env(env: &mut Env) {
     env.set(
         "vals",
         make_fn_val(|mut args, _| {
-            let map = args.next().unwrap().try_into_map()?;
+            ensure_arity(&args, "vals", 1..=1)?;
+            let map = args.next().unwrap();
+            if let Value::SortedMap(map, _) = map {
+                return Ok(Value::List(
+                    map.entries.iter().map(|(_, v)| v.clone()).collect(),
+                    Box::new(Value::Nil),
+                ));
+            }
+            let map = map.try_into_map()?;
             Ok(Value::List(
-                map.into_values().collect(),
+                map.values().cloned().collect(),
                 Box::new(Value::Nil),
             ))
         }),
@@ -483,35 +624,48 @@ pub fn init_env(env: &mut Env) {
                 std::time::SystemTime::now()
                     .duration_since(std::time::UNIX_EPOCH)
                     .unwrap()
-                    .as_millis() as i32,
+                    .as_millis()
+                    .into(),
             ))
         }),
     );
     env.set(
         "meta",
-        make_fn_val(|mut args, _| match args.next().unwrap() {
-            Value::List(_, m)
-            | Value::Vec(_, m)
-            | Value::Map(_, m)
-            | Value::HostFn(_, m)
-            | Value::Closure(_, m) => Ok(*m),
-            v => Err(runtime_errors::not_a(
-                "value with metadata (list, vec or function)",
-                &v,
-            )),
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "meta", 1..=1)?;
+            match args.next().unwrap() {
+                Value::List(_, m)
+                | Value::Vec(_, m)
+                | Value::Map(_, m)
+                | Value::Set(_, m)
+                | Value::SortedMap(_, m)
+                | Value::SortedSet(_, m)
+                | Value::LazySeq(_, m)
+                | Value::HostFn(_, m)
+                | Value::Closure(_, m) => Ok(*m),
+                v => Err(runtime_errors::not_a(
+                    "value with metadata (list, vec or function)",
+                    &v,
+                )),
+            }
         }),
     );
     env.set(
         "with-meta",
         make_fn_val(|mut args, _| {
+            ensure_arity(&args, "with-meta", 2..=2)?;
             let mut v = args.next().unwrap();
             match &mut v {
                 Value::List(_, m)
                 | Value::Vec(_, m)
                 | Value::Map(_, m)
+                | Value::Set(_, m)
+                | Value::SortedMap(_, m)
+                | Value::SortedSet(_, m)
+                | Value::LazySeq(_, m)
                 | Value::HostFn(_, m)
                 | Value::Closure(_, m) => {
-                    *m = Box::new(args.next().unwrap());
+                    **m = args.next().unwrap();
                     Ok(v)
                 }
                 v => Err(runtime_errors::not_a(
@@ -524,6 +678,7 @@ pub fn init_env(env: &mut Env) {
     env.set(
         "fn?",
         make_fn_val(|mut args, _| {
+            ensure_arity(&args, "fn?", 1..=1)?;
             let arg = args.next().unwrap();
             Ok(Value::Bool(
                 matches!(arg, Value::HostFn(_, _))
@@ -537,24 +692,17 @@ pub fn init_env(env: &mut Env) {
     env.set(
         "string?",
         make_fn_val(|mut args, _| {
+            ensure_arity(&args, "string?", 1..=1)?;
             Ok(Value::Bool(matches!(
                 args.next().unwrap(),
                 Value::String(_)
             )))
         }),
     );
-    env.set(
-        "number?",
-        make_fn_val(|mut args, _| {
-            Ok(Value::Bool(matches!(
-                args.next().unwrap(),
-                Value::Number(_)
-            )))
-        }),
-    );
     env.set(
         "macro?",
         make_fn_val(|mut args, _| {
+            ensure_arity(&args, "macro?", 1..=1)?;
             Ok(Value::Bool(matches!(
                 args.next().unwrap(),
                 Value::Closure(c, _) if c.is_macro
@@ -563,36 +711,247 @@ pub fn init_env(env: &mut Env) {
     );
     env.set(
         "seq",
-        make_fn_val(|mut args, _| match args.next().unwrap() {
-            Value::List(l, _) | Value::Vec(l, _) if l.is_empty() => Ok(Value::Nil),
-            Value::String(s) if s.is_empty() => Ok(Value::Nil),
-            Value::Nil => Ok(Value::Nil),
-            Value::List(l, _) | Value::Vec(l, _) => Ok(Value::List(l, Box::new(Value::Nil))),
-            Value::String(s) => Ok(Value::List(
-                s.chars().map(|c| Value::String(c.into())).collect(),
-                Box::new(Value::Nil),
-            )),
-            v => Err(runtime_errors::not_a("valid argument for seq", &v)),
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "seq", 1..=1)?;
+            args.next().unwrap().seq()
         }),
     );
     env.set(
         "conj",
-        make_fn_val(|mut args, _| match args.next().unwrap() {
-            Value::List(l, _) => {
-                let mut new_list: Vec<Value> = args.rev().collect();
-                new_list.extend(l.into_iter());
-                Ok(Value::List(new_list, Box::new(Value::Nil)))
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "conj", 1..)?;
+            let coll = args.next().unwrap();
+            conj(coll, args, &env)
+        }),
+    );
+    env.set(
+        "peek",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "peek", 1..=1)?;
+            match args.next().unwrap() {
+                Value::List(l, _) => Ok(l.into_iter().next().unwrap_or(Value::Nil)),
+                Value::Vec(v, _) => Ok(v.last().cloned().unwrap_or(Value::Nil)),
+                Value::Nil => Ok(Value::Nil),
+                v => Err(runtime_errors::not_a("list or vec", &v)),
             }
-            Value::Vec(l, _) => {
-                let mut new_list: Vec<Value> = l.to_vec();
-                new_list.extend(args);
-                Ok(Value::Vec(new_list, Box::new(Value::Nil)))
+        }),
+    );
+    env.set(
+        "pop",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "pop", 1..=1)?;
+            match args.next().unwrap() {
+                Value::List(l, _) if l.is_empty() => {
+                    Err(runtime_errors::error_to_string("can't pop an empty list"))
+                }
+                Value::Vec(v, _) if v.is_empty() => {
+                    Err(runtime_errors::error_to_string("can't pop an empty vector"))
+                }
+                Value::List(mut l, _) => {
+                    l.remove(0);
+                    Ok(Value::List(l, Box::new(Value::Nil)))
+                }
+                Value::Vec(mut v, _) => {
+                    v.pop();
+                    Ok(Value::Vec(v, Box::new(Value::Nil)))
+                }
+                Value::Nil => Ok(Value::Nil),
+                v => Err(runtime_errors::not_a("list or vec", &v)),
+            }
+        }),
+    );
+    env.set(
+        "subvec",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "subvec", 2..=3)?;
+            let v = match args.next().unwrap() {
+                Value::Vec(v, _) => v,
+                v => return Err(runtime_errors::not_a("vector", &v)),
+            };
+            let start = args.next().unwrap().try_into_int()?;
+            let end = match args.next() {
+                Some(end) => end.try_into_int()?,
+                None => Int::from(v.len()),
+            };
+            match (start.to_usize(), end.to_usize()) {
+                (Some(s), Some(e)) if s <= e && e <= v.len() => {
+                    Ok(Value::Vec(v.subvec(s..e), Box::new(Value::Nil)))
+                }
+                (Some(s), _) if s <= v.len() => Err(runtime_errors::out_of_bounds(v.len(), end)),
+                _ => Err(runtime_errors::out_of_bounds(v.len(), start)),
             }
-            v => Err(runtime_errors::not_a("list or vec", &v)),
         }),
     );
 }
 
+/// Looks up `key` in a map, a vector (by index) or a set. Returns `None` if it isn't there,
+/// or if `coll` can't be looked up in, like in Clojure.
+fn get(coll: &Value, key: &Value, env: &Rc<RefCell<Env>>) -> RuntimeResult<Option<Value>> {
+    Ok(match coll {
+        Value::Map(map, _) => map.get(key).cloned(),
+        Value::SortedMap(map, _) => map.get(key, env)?.map(|(_, v)| v.clone()),
+        Value::Vec(v, _) => match key {
+            Value::Number(i) => i.to_usize().and_then(|i| v.get(i)).cloned(),
+            _ => None,
+        },
+        Value::Set(set, _) => set.get(key).cloned(),
+        Value::SortedSet(set, _) => set.get(key, env)?.map(|(k, _)| k.clone()),
+        _ => None,
+    })
+}
+
+/// Calls a keyword, map, vector or set as a function: `(:k m)`, `(m :k)`, `(v 0)` and `(s x)`
+/// look up the argument like `get`, and return the optional second argument if it's missing.
+pub fn call_collection(
+    coll: Value,
+    mut args: Args,
+    env: &Rc<RefCell<Env>>,
+) -> RuntimeResult<Value> {
+    let name = match &coll {
+        Value::Keyword(_) => coll.to_string(),
+        Value::Map(_, _) | Value::SortedMap(_, _) => "map".to_string(),
+        Value::Vec(_, _) => "vector".to_string(),
+        Value::Set(_, _) | Value::SortedSet(_, _) => "set".to_string(),
+        v => return Err(runtime_errors::not_a("function", v)),
+    };
+    ensure_arity(&args, &name, 1..=2)?;
+    let arg = args.next().unwrap();
+    let found = match coll {
+        key @ Value::Keyword(_) => get(&arg, &key, env)?,
+        coll => get(&coll, &arg, env)?,
+    };
+    Ok(found.unwrap_or_else(|| args.next().unwrap_or(Value::Nil)))
+}
+
+/// Associates `key` with `value` in a map, or replaces the element at index `key` of a vector.
+/// The index may be the length of the vector, which appends `value`. `nil` is an empty map.
+fn assoc(coll: Value, key: Value, value: Value, env: &Rc<RefCell<Env>>) -> RuntimeResult<Value> {
+    match coll {
+        Value::Nil => assoc(
+            Value::Map(PersistentHashMap::new(), Box::new(Value::Nil)),
+            key,
+            value,
+            env,
+        ),
+        Value::Map(mut map, _) => {
+            map.insert(key, value);
+            Ok(Value::Map(map, Box::new(Value::Nil)))
+        }
+        Value::SortedMap(mut map, _) => {
+            map.insert(key, value, env)?;
+            Ok(Value::SortedMap(map, Box::new(Value::Nil)))
+        }
+        Value::Vec(mut v, _) => {
+            let index = key.try_into_int()?;
+            match index.to_usize() {
+                Some(i) if i < v.len() => v.set(i, value),
+                Some(i) if i == v.len() => v.push(value),
+                _ => return Err(runtime_errors::out_of_bounds(v.len(), index)),
+            }
+            Ok(Value::Vec(v, Box::new(Value::Nil)))
+        }
+        v => Err(runtime_errors::not_a("map or vector", &v)),
+    }
+}
+
+/// Replaces the value at the path `keys` in nested maps and vectors with the result of `f`
+/// applied to the old value. Missing levels are created as maps.
+fn update_in(
+    coll: Value,
+    keys: &[Value],
+    env: &Rc<RefCell<Env>>,
+    f: &mut dyn FnMut(Value) -> RuntimeResult<Value>,
+) -> RuntimeResult<Value> {
+    match keys.split_first() {
+        None => f(coll),
+        Some((key, rest)) => {
+            let inner = get(&coll, key, env)?.unwrap_or(Value::Nil);
+            let updated = update_in(inner, rest, env, f)?;
+            assoc(coll, key.clone(), updated, env)
+        }
+    }
+}
+
+/// Adds `items` to `coll` where it's most efficient: to the front of lists and sequences,
+/// and to the end of vectors. Items added to maps must be `[key value]` entries or maps.
+fn conj(
+    coll: Value,
+    items: impl DoubleEndedIterator<Item = Value>,
+    env: &Rc<RefCell<Env>>,
+) -> RuntimeResult<Value> {
+    match coll {
+        Value::List(l, _) => {
+            let mut new_list: Vec<Value> = items.rev().collect();
+            new_list.extend(l);
+            Ok(Value::List(new_list, Box::new(Value::Nil)))
+        }
+        Value::Nil => conj(Value::List(Vec::new(), Box::new(Value::Nil)), items, env),
+        Value::Vec(mut v, _) => {
+            v.extend(items);
+            Ok(Value::Vec(v, Box::new(Value::Nil)))
+        }
+        Value::Set(mut set, _) => {
+            set.extend(items);
+            Ok(Value::Set(set, Box::new(Value::Nil)))
+        }
+        Value::SortedSet(mut set, _) => {
+            for v in items {
+                set.insert(v, (), env)?;
+            }
+            Ok(Value::SortedSet(set, Box::new(Value::Nil)))
+        }
+        Value::Map(mut map, _) => {
+            for item in items {
+                for (k, v) in map_entries(item)? {
+                    map.insert(k, v);
+                }
+            }
+            Ok(Value::Map(map, Box::new(Value::Nil)))
+        }
+        Value::SortedMap(mut map, _) => {
+            for item in items {
+                for (k, v) in map_entries(item)? {
+                    map.insert(k, v, env)?;
+                }
+            }
+            Ok(Value::SortedMap(map, Box::new(Value::Nil)))
+        }
+        seq @ Value::LazySeq(_, _) => Ok(items.fold(seq, |seq, v| lazy_seq::cons(v, seq))),
+        v => Err(runtime_errors::not_a("collection", &v)),
+    }
+}
+
+/// The entries to add to a map for an item given to `conj`: a `[key value]` vector or a map.
+fn map_entries(item: Value) -> RuntimeResult<Vec<(Value, Value)>> {
+    match item {
+        Value::Vec(v, _) if v.len() == 2 => {
+            Ok(vec![(v.get(0).unwrap().clone(), v.get(1).unwrap().clone())])
+        }
+        map @ (Value::Map(_, _) | Value::SortedMap(_, _)) => Ok(map
+            .try_iter_map()
+            .unwrap()
+            .map(|(k, v)| (k.clone(), v.clone()))
+            .collect()),
+        v => Err(runtime_errors::not_a("map entry ([key value] vector)", &v)),
+    }
+}
+
+fn is_truthy(v: &Value) -> bool {
+    !matches!(v, Value::Bool(false) | Value::Nil)
+}
+
+fn ensure_arity(
+    args: &impl ExactSizeIterator,
+    name: &str,
+    arity: impl RangeBounds<usize>,
+) -> RuntimeResult<()> {
+    if arity.contains(&args.len()) {
+        Ok(())
+    } else {
+        Err(runtime_errors::wrong_arity(name, args.len()))
+    }
+}
+
 fn ensure_even_args(args: &impl ExactSizeIterator) -> RuntimeResult<()> {
     if args.len() % 2 != 0 {
         Err(runtime_errors::error_to_string(format!(
@@ -603,3 +962,135 @@ fn ensure_even_args(args: &impl ExactSizeIterator) -> RuntimeResult<()> {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{eval, reader::read_str};
+
+    pub fn eval_str(input: &str) -> RuntimeResult<Value> {
+        let env = Rc::new(RefCell::new(Env::new(None)));
+        init_env(&mut env.borrow_mut());
+        let value = eval(read_str(input).unwrap(), env)?;
+        value.realize()?;
+        Ok(value)
+    }
+
+    /// Compares the printed forms, so that lists, vectors and strings aren't confused.
+    pub fn assert_evals_to(input: &str, expected: &str) {
+        assert_eq!(eval_str(input).unwrap().to_string(), expected, "{}", input);
+    }
+
+    pub fn assert_fails(input: &str) {
+        assert!(eval_str(input).is_err(), "{}", input);
+    }
+
+    #[test]
+    fn nested_lookups() {
+        assert_evals_to("(get-in {:a [1 {:b 2}]} [:a 1 :b])", "2");
+        assert_evals_to("(get-in [[1 2] [3 4]] [1 0])", "3");
+        assert_evals_to("(get-in {:a 1} [:x :y])", "nil");
+        assert_evals_to("(get-in {:a 1} [:x] :nf)", ":nf");
+        assert_evals_to("(get-in [[1 2]] [0 5] :nf)", ":nf");
+        // only missing keys give the default, not nil values
+        assert_evals_to("(get-in {:a nil} [:a] :nf)", "nil");
+        assert_evals_to("(get-in {:a 1} [])", "{:a 1}");
+        assert_evals_to("(get-in nil [:a])", "nil");
+        assert_evals_to("(find {:a 1} :a)", "[:a 1]");
+        assert_evals_to("(find {:a 1} :b)", "nil");
+        assert_evals_to("(find [5] 0)", "[0 5]");
+    }
+
+    #[test]
+    fn nested_updates() {
+        assert_evals_to("(assoc-in {} [:a :b] 1)", "{:a {:b 1}}");
+        assert_evals_to("(assoc-in [[1 2]] [0 1] 9)", "[[1 9]]");
+        assert_evals_to("(assoc-in {:a [1]} [:a 1] 2)", "{:a [1 2]}");
+        assert_evals_to("(assoc-in nil [:a 0] 1)", "{:a {0 1}}");
+        assert_fails("(assoc-in [1] [5] 2)");
+        assert_fails("(assoc-in [[1]] [0 3] 2)");
+        assert_evals_to("(update {:a 1} :a inc)", "{:a 2}");
+        assert_evals_to("(update {:a 1} :a + 10 20)", "{:a 31}");
+        assert_evals_to("(update [1 2] 0 inc)", "[2 2]");
+        assert_evals_to("(update nil :a (fn* [x] (nil? x)))", "{:a true}");
+        assert_evals_to("(update-in {:a {:b 1}} [:a :b] inc)", "{:a {:b 2}}");
+        assert_evals_to("(update-in {:a [1 2]} [:a 1] + 5)", "{:a [1 7]}");
+        assert_evals_to("(update-in {} [:x :y] (fn* [v] (if v 1 0)))", "{:x {:y 0}}");
+        assert_fails("(update-in [1] [3] inc)");
+        assert_evals_to("(dissoc {:a 1 :b 2} :a)", "{:b 2}");
+        assert_evals_to("(dissoc nil :a)", "nil");
+        assert_fails("(dissoc [1] 0)");
+    }
+
+    #[test]
+    fn merging() {
+        assert_evals_to("(= (merge {:a 1} {:b 2} {:a 3}) {:a 3 :b 2})", "true");
+        assert_evals_to("(merge nil {:a 1})", "{:a 1}");
+        assert_evals_to("(merge {:a 1} nil)", "{:a 1}");
+        assert_evals_to("(merge nil nil)", "nil");
+        assert_evals_to("(merge)", "nil");
+        assert_evals_to("(= (merge-with + {:a 1} {:a 2 :b 3}) {:a 3 :b 3})", "true");
+        assert_evals_to("(merge-with + nil {:a 1})", "{:a 1}");
+        assert_evals_to("(merge-with +)", "nil");
+        assert_evals_to("(select-keys {:a 1 :b 2} [:a :c])", "{:a 1}");
+        assert_evals_to("(select-keys nil [:a])", "{}");
+        assert_evals_to("(select-keys [5 6] [1])", "{1 6}");
+    }
+
+    #[test]
+    fn collections_are_callable() {
+        assert_evals_to("(:k {:k 1})", "1");
+        assert_evals_to("(:k {} 2)", "2");
+        assert_evals_to("(:k nil)", "nil");
+        assert_evals_to("(:k [1])", "nil");
+        assert_evals_to("({:a 1} :a)", "1");
+        assert_evals_to("({:a 1} :b 3)", "3");
+        assert_evals_to("([5 6] 1)", "6");
+        // out-of-range indices are missing keys, as with `get`
+        assert_evals_to("([5 6] 9)", "nil");
+        assert_evals_to("([5 6] -1 :d)", ":d");
+        assert_evals_to("(#{1 2} 2)", "2");
+        assert_evals_to("(#{1} 3)", "nil");
+        assert_evals_to("(#{1} 3 :d)", ":d");
+        assert_evals_to("(map :a [{:a 1} {:a 2}])", "(1 2)");
+        assert_evals_to("(apply {:a 1} [:a])", "1");
+        assert_evals_to("(let* (v [1 2]) (v 0))", "1");
+        for input in [
+            "(:k)",
+            "(:k {} 1 2)",
+            "({})",
+            "({} 1 2 3)",
+            "([1])",
+            "([1] 0 1 2)",
+            "(#{})",
+            "(#{} 1 2 3)",
+            "('(1) 0)",
+            "(1 2)",
+        ] {
+            assert_fails(input);
+        }
+    }
+
+    #[test]
+    fn core_functions_check_arity() {
+        for form in &[
+            "(count)",
+            "(empty? [] [])",
+            "(= 1)",
+            "(cons 1)",
+            "(first)",
+            "(rest)",
+            "(throw)",
+            "(atom)",
+            "(reset! (atom 1))",
+            "(swap! (atom 1))",
+            "(contains? {})",
+            "(with-meta [])",
+            "(dissoc)",
+            "(seq)",
+            "(nil?)",
+        ] {
+            assert_fails(form);
+        }
+    }
+}
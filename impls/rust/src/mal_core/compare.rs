@@ -0,0 +1,112 @@
+use std::{cell::RefCell, cmp::Ordering, rc::Rc};
+
+use super::{ensure_arity, make_fn_val};
+use crate::{
+    env::Env,
+    eval_fn_no_tco,
+    number::Int,
+    runtime_errors::{self, RuntimeResult},
+    value::Value,
+};
+
+pub fn init_env(env: &mut Env) {
+    env.set(
+        "compare",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "compare", 2..=2)?;
+            let ordering = args.next().unwrap().cmp(&args.next().unwrap());
+            Ok(Value::Number(Int::Small(ordering as i64)))
+        }),
+    );
+    env.set(
+        "sort",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "sort", 1..=2)?;
+            let comparator = if args.len() == 2 { args.next() } else { None };
+            let coll = args.next().unwrap().seq_elements()?;
+            let sorted = match comparator {
+                Some(f) => merge_sort(coll, &mut |a, b| call_comparator(&f, a, b, &env))?,
+                None => merge_sort(coll, &mut |a, b| Ok(a.cmp(b)))?,
+            };
+            Ok(Value::List(sorted, Box::new(Value::Nil)))
+        }),
+    );
+    env.set(
+        "sort-by",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "sort-by", 2..=3)?;
+            let key_fn = args.next().unwrap();
+            let comparator = if args.len() == 2 { args.next() } else { None };
+            let coll = args.next().unwrap().seq_elements()?;
+            // compute every key once instead of on each comparison
+            let keyed = coll
+                .into_iter()
+                .map(|v| {
+                    Ok((
+                        eval_fn_no_tco(key_fn.clone(), vec![v.clone()], env.clone())?,
+                        v,
+                    ))
+                })
+                .collect::<RuntimeResult<Vec<_>>>()?;
+            let sorted = match comparator {
+                Some(f) => merge_sort(keyed, &mut |a, b| call_comparator(&f, &a.0, &b.0, &env))?,
+                None => merge_sort(keyed, &mut |a, b| Ok(a.0.cmp(&b.0)))?,
+            };
+            Ok(Value::List(
+                sorted.into_iter().map(|(_, v)| v).collect(),
+                Box::new(Value::Nil),
+            ))
+        }),
+    );
+}
+
+/// Calls a user supplied comparator. Like in Clojure, it may either return a number
+/// (negative, zero or positive) or be a "less than" predicate like `<` that returns a boolean.
+pub(super) fn call_comparator(
+    f: &Value,
+    a: &Value,
+    b: &Value,
+    env: &Rc<RefCell<Env>>,
+) -> RuntimeResult<Ordering> {
+    let call =
+        |a: &Value, b: &Value| eval_fn_no_tco(f.clone(), vec![a.clone(), b.clone()], env.clone());
+    match call(a, b)? {
+        n if n.is_number() => n
+            .numeric_cmp(&Value::Number(Int::Small(0)))
+            .ok_or_else(|| runtime_errors::error_to_string("comparator returned NaN")),
+        Value::Bool(true) => Ok(Ordering::Less),
+        Value::Bool(false) | Value::Nil => match call(b, a)? {
+            Value::Bool(false) | Value::Nil => Ok(Ordering::Equal),
+            _ => Ok(Ordering::Greater),
+        },
+        v => Err(runtime_errors::not_a(
+            "number or boolean from comparator",
+            &v,
+        )),
+    }
+}
+
+/// A stable merge sort. Unlike `slice::sort_by`, it can propagate errors from the comparator
+/// and doesn't panic if a user supplied comparator isn't a total order.
+fn merge_sort<T>(
+    mut items: Vec<T>,
+    cmp: &mut impl FnMut(&T, &T) -> RuntimeResult<Ordering>,
+) -> RuntimeResult<Vec<T>> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let mut left = merge_sort(items, cmp)?.into_iter().peekable();
+    let mut right = merge_sort(right, cmp)?.into_iter().peekable();
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        if cmp(r, l)? == Ordering::Less {
+            merged.push(right.next().unwrap());
+        } else {
+            merged.push(left.next().unwrap());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
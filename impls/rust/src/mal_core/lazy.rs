@@ -0,0 +1,583 @@
+use std::{cell::RefCell, cmp::Ordering, rc::Rc};
+
+use super::{ensure_arity, is_truthy, make_fn_val};
+use crate::{
+    env::Env,
+    eval_fn_no_tco,
+    hash_set::PersistentHashSet,
+    lazy_seq::{cons, lazy},
+    number::Int,
+    runtime_errors::{self, RuntimeResult},
+    value::{SeqIter, Value},
+};
+
+type Args = std::vec::IntoIter<Value>;
+
+pub fn init_env(env: &mut Env) {
+    env.set(
+        "map",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "map", 2..)?;
+            let f = args.next().unwrap();
+            let (colls, is_lazy) = iters(args)?;
+            realize_unless(is_lazy, map(f, colls, env))
+        }),
+    );
+    env.set(
+        "mapcat",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "mapcat", 2..)?;
+            let f = args.next().unwrap();
+            let (colls, is_lazy) = iters(args)?;
+            let seqs = SeqIter::new(map(f, colls, env))?;
+            realize_unless(is_lazy, concat(SeqIter::new(Value::Nil)?, seqs))
+        }),
+    );
+    env.set(
+        "filter",
+        make_fn_val(|args, env| filter_builtin(args, "filter", true, env)),
+    );
+    env.set(
+        "remove",
+        make_fn_val(|args, env| filter_builtin(args, "remove", false, env)),
+    );
+    env.set(
+        "keep",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "keep", 2..=2)?;
+            let f = args.next().unwrap();
+            let coll = args.next().unwrap();
+            let is_lazy = is_lazy(&coll);
+            realize_unless(is_lazy, keep(f, SeqIter::new(coll)?, env))
+        }),
+    );
+    env.set(
+        "interleave",
+        make_fn_val(|args, _| {
+            let (colls, is_lazy) = iters(args)?;
+            if colls.is_empty() {
+                return Ok(Value::List(Vec::new(), Box::new(Value::Nil)));
+            }
+            realize_unless(is_lazy, interleave(colls))
+        }),
+    );
+    env.set(
+        "distinct",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "distinct", 1..=1)?;
+            let coll = args.next().unwrap();
+            let is_lazy = is_lazy(&coll);
+            realize_unless(
+                is_lazy,
+                distinct(SeqIter::new(coll)?, PersistentHashSet::new()),
+            )
+        }),
+    );
+    env.set(
+        "partition",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "partition", 2..=4)?;
+            let n = count_arg(args.next().unwrap())?;
+            let step = match args.len() {
+                1 => n,
+                _ => count_arg(args.next().unwrap())?,
+            };
+            let pad = match args.len() {
+                2 => Some(args.next().unwrap().seq_elements()?),
+                _ => None,
+            };
+            if n == 0 || step == 0 {
+                return Err(runtime_errors::error_to_string(
+                    "partition size and step must be positive",
+                ));
+            }
+            let coll = args.next().unwrap();
+            let is_lazy = is_lazy(&coll);
+            let partitions = partition(Partition { n, step, pad }, SeqIter::new(coll)?, Vec::new());
+            realize_unless(is_lazy, partitions)
+        }),
+    );
+    env.set(
+        "take",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "take", 2..=2)?;
+            let n = count_arg(args.next().unwrap())?;
+            let coll = args.next().unwrap();
+            let is_lazy = is_lazy(&coll);
+            realize_unless(is_lazy, take(n, SeqIter::new(coll)?))
+        }),
+    );
+    env.set(
+        "drop",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "drop", 2..=2)?;
+            let n = count_arg(args.next().unwrap())?;
+            let coll = args.next().unwrap();
+            let is_lazy = is_lazy(&coll);
+            let mut coll = SeqIter::new(coll)?;
+            let dropped = lazy(move || {
+                for _ in 0..n {
+                    if coll.next().transpose()?.is_none() {
+                        break;
+                    }
+                }
+                Ok(coll.into_seq())
+            });
+            realize_unless(is_lazy, dropped)
+        }),
+    );
+    env.set(
+        "take-while",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "take-while", 2..=2)?;
+            let pred = args.next().unwrap();
+            let coll = args.next().unwrap();
+            let is_lazy = is_lazy(&coll);
+            realize_unless(is_lazy, take_while(pred, SeqIter::new(coll)?, env))
+        }),
+    );
+    env.set(
+        "range",
+        make_fn_val(|args, _| {
+            ensure_arity(&args, "range", 0..=3)?;
+            let args = args
+                .map(|n| {
+                    if n.is_number() {
+                        Ok(n)
+                    } else {
+                        Err(runtime_errors::not_a("number", &n))
+                    }
+                })
+                .collect::<RuntimeResult<Vec<_>>>()?;
+            let (zero, one) = (Value::Number(Int::Small(0)), Value::Number(Int::Small(1)));
+            let mut args = args.into_iter();
+            Ok(match (args.next(), args.next(), args.next()) {
+                (None, _, _) => range(zero, None, one),
+                (Some(end), None, _) => range(zero, Some(end), one),
+                (Some(start), Some(end), step) => range(start, Some(end), step.unwrap_or(one)),
+            })
+        }),
+    );
+    env.set(
+        "iterate",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "iterate", 2..=2)?;
+            let f = args.next().unwrap();
+            Ok(iterate(f, args.next().unwrap(), env))
+        }),
+    );
+    env.set(
+        "repeat",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "repeat", 1..=2)?;
+            let n = match args.len() {
+                2 => Some(count_arg(args.next().unwrap())?),
+                _ => None,
+            };
+            Ok(repeat(args.next().unwrap(), n))
+        }),
+    );
+    env.set(
+        "cycle",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "cycle", 1..=1)?;
+            let items = args.next().unwrap().seq_elements()?;
+            if items.is_empty() {
+                return Ok(Value::List(items, Box::new(Value::Nil)));
+            }
+            Ok(cycle(Rc::new(items), 0))
+        }),
+    );
+    env.set(
+        "realized?",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "realized?", 1..=1)?;
+            match args.next().unwrap() {
+                Value::LazySeq(seq, _) => Ok(Value::Bool(seq.is_realized())),
+                v => Err(runtime_errors::not_a("lazy sequence", &v)),
+            }
+        }),
+    );
+    env.set(
+        "doall",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "doall", 1..=1)?;
+            let coll = args.next().unwrap();
+            for v in SeqIter::new(coll.clone())? {
+                v?;
+            }
+            Ok(coll)
+        }),
+    );
+}
+
+/// A lazy sequence of the elements of `current`, followed by the elements of each
+/// collection in `rest`.
+pub(super) fn concat(mut current: SeqIter, mut rest: SeqIter) -> Value {
+    lazy(move || loop {
+        if let Some(v) = current.next() {
+            return Ok(cons(v?, concat(current, rest)));
+        }
+        match rest.next() {
+            Some(next) => current = SeqIter::new(next?)?,
+            None => return Ok(Value::Nil),
+        }
+    })
+}
+
+/// Iterators over the collections, and whether any of them is lazy.
+fn iters(colls: Args) -> RuntimeResult<(Vec<SeqIter>, bool)> {
+    let is_lazy = colls.as_slice().iter().any(is_lazy);
+    Ok((
+        colls.map(SeqIter::new).collect::<RuntimeResult<_>>()?,
+        is_lazy,
+    ))
+}
+
+fn is_lazy(coll: &Value) -> bool {
+    matches!(coll, Value::LazySeq(_, _))
+}
+
+/// Over lists and vectors, the sequence functions return lists, so that errors from
+/// the functions they call are raised by the call itself, like mal's tests expect
+/// from `(try* (map throw ...) ...)`. Only lazy inputs give lazy results.
+fn realize_unless(is_lazy: bool, seq: Value) -> RuntimeResult<Value> {
+    if is_lazy {
+        Ok(seq)
+    } else {
+        Ok(Value::List(
+            seq.try_into_list_or_vec()?,
+            Box::new(Value::Nil),
+        ))
+    }
+}
+
+/// A number of elements: negative counts are 0, and counts that don't fit are unbounded.
+fn count_arg(n: Value) -> RuntimeResult<usize> {
+    let n = n.try_into_int()?;
+    Ok(if n < Int::Small(0) {
+        0
+    } else {
+        n.to_usize().unwrap_or(usize::MAX)
+    })
+}
+
+/// Calls `f` with an element of each collection, until one of them runs out.
+fn map(f: Value, mut colls: Vec<SeqIter>, env: Rc<RefCell<Env>>) -> Value {
+    lazy(move || {
+        let mut args = Vec::with_capacity(colls.len());
+        for coll in &mut colls {
+            match coll.next() {
+                Some(v) => args.push(v?),
+                None => return Ok(Value::Nil),
+            }
+        }
+        let mapped = eval_fn_no_tco(f.clone(), args, env.clone())?;
+        Ok(cons(mapped, map(f, colls, env)))
+    })
+}
+
+/// `filter`, or `remove` if not `wanted`.
+fn filter_builtin(
+    mut args: Args,
+    name: &str,
+    wanted: bool,
+    env: Rc<RefCell<Env>>,
+) -> RuntimeResult<Value> {
+    ensure_arity(&args, name, 2..=2)?;
+    let pred = args.next().unwrap();
+    let coll = args.next().unwrap();
+    let is_lazy = is_lazy(&coll);
+    realize_unless(is_lazy, filter(pred, SeqIter::new(coll)?, wanted, env))
+}
+
+/// The elements for which `pred` is truthy, or falsy if not `wanted`.
+fn filter(pred: Value, mut coll: SeqIter, wanted: bool, env: Rc<RefCell<Env>>) -> Value {
+    lazy(move || {
+        while let Some(v) = coll.next() {
+            let v = v?;
+            let result = eval_fn_no_tco(pred.clone(), vec![v.clone()], env.clone())?;
+            if is_truthy(&result) == wanted {
+                return Ok(cons(v, filter(pred, coll, wanted, env)));
+            }
+        }
+        Ok(Value::Nil)
+    })
+}
+
+/// The results of `f` that aren't nil.
+fn keep(f: Value, mut coll: SeqIter, env: Rc<RefCell<Env>>) -> Value {
+    lazy(move || {
+        while let Some(v) = coll.next() {
+            match eval_fn_no_tco(f.clone(), vec![v?], env.clone())? {
+                Value::Nil => {}
+                result => return Ok(cons(result, keep(f, coll, env))),
+            }
+        }
+        Ok(Value::Nil)
+    })
+}
+
+/// The first element of each collection, then the second, and so on, until one runs out.
+fn interleave(mut colls: Vec<SeqIter>) -> Value {
+    lazy(move || {
+        let mut round = Vec::with_capacity(colls.len());
+        for coll in &mut colls {
+            match coll.next() {
+                Some(v) => round.push(v?),
+                None => return Ok(Value::Nil),
+            }
+        }
+        let rest = interleave(colls);
+        Ok(round.into_iter().rev().fold(rest, |rest, v| cons(v, rest)))
+    })
+}
+
+fn distinct(mut coll: SeqIter, mut seen: PersistentHashSet<Value>) -> Value {
+    lazy(move || {
+        while let Some(v) = coll.next() {
+            let v = v?;
+            if seen.insert(v.clone()) {
+                return Ok(cons(v, distinct(coll, seen)));
+            }
+        }
+        Ok(Value::Nil)
+    })
+}
+
+struct Partition {
+    n: usize,
+    step: usize,
+    /// Elements to complete the last partition with. Without them, it's dropped if incomplete.
+    pad: Option<Vec<Value>>,
+}
+
+/// Lists of `n` elements, each starting `step` elements after the previous one.
+/// `buffered` holds the elements of the next partition that were already taken from `coll`.
+fn partition(options: Partition, mut coll: SeqIter, mut buffered: Vec<Value>) -> Value {
+    lazy(move || {
+        while buffered.len() < options.n {
+            match coll.next() {
+                Some(v) => buffered.push(v?),
+                None => break,
+            }
+        }
+        if buffered.len() < options.n {
+            return Ok(match options.pad {
+                Some(pad) if !buffered.is_empty() => {
+                    let missing = options.n - buffered.len();
+                    buffered.extend(pad.into_iter().take(missing));
+                    cons(Value::List(buffered, Box::new(Value::Nil)), Value::Nil)
+                }
+                _ => Value::Nil,
+            });
+        }
+        let chunk = Value::List(buffered.clone(), Box::new(Value::Nil));
+        if options.step < options.n {
+            buffered.drain(..options.step);
+        } else {
+            buffered.clear();
+            for _ in options.n..options.step {
+                if coll.next().transpose()?.is_none() {
+                    break;
+                }
+            }
+        }
+        Ok(cons(chunk, partition(options, coll, buffered)))
+    })
+}
+
+fn take(n: usize, mut coll: SeqIter) -> Value {
+    lazy(move || {
+        if n == 0 {
+            return Ok(Value::Nil);
+        }
+        match coll.next() {
+            Some(v) => Ok(cons(v?, take(n - 1, coll))),
+            None => Ok(Value::Nil),
+        }
+    })
+}
+
+fn take_while(pred: Value, mut coll: SeqIter, env: Rc<RefCell<Env>>) -> Value {
+    lazy(move || match coll.next() {
+        Some(v) => {
+            let v = v?;
+            if is_truthy(&eval_fn_no_tco(pred.clone(), vec![v.clone()], env.clone())?) {
+                Ok(cons(v, take_while(pred, coll, env)))
+            } else {
+                Ok(Value::Nil)
+            }
+        }
+        None => Ok(Value::Nil),
+    })
+}
+
+/// The numbers from `start` (inclusive) to `end` (exclusive), or without end.
+/// Like in Clojure, a step of 0 repeats `start` forever, unless it's equal to `end`.
+fn range(start: Value, end: Option<Value>, step: Value) -> Value {
+    lazy(move || {
+        if let Some(end) = &end {
+            let done = match step.numeric_cmp(&Value::Number(Int::Small(0))) {
+                Some(Ordering::Less) => start.numeric_cmp(end) != Some(Ordering::Greater),
+                Some(Ordering::Greater) => start.numeric_cmp(end) != Some(Ordering::Less),
+                _ => start.numeric_cmp(end) == Some(Ordering::Equal),
+            };
+            if done {
+                return Ok(Value::Nil);
+            }
+        }
+        let next = (start.clone() + step.clone())?;
+        Ok(cons(start, range(next, end, step)))
+    })
+}
+
+fn iterate(f: Value, x: Value, env: Rc<RefCell<Env>>) -> Value {
+    let rest = {
+        let x = x.clone();
+        lazy(move || {
+            let next = eval_fn_no_tco(f.clone(), vec![x], env.clone())?;
+            Ok(iterate(f, next, env))
+        })
+    };
+    cons(x, rest)
+}
+
+fn repeat(x: Value, n: Option<usize>) -> Value {
+    lazy(move || {
+        if n == Some(0) {
+            return Ok(Value::Nil);
+        }
+        Ok(cons(x.clone(), repeat(x, n.map(|n| n - 1))))
+    })
+}
+
+fn cycle(items: Rc<Vec<Value>>, i: usize) -> Value {
+    lazy(move || {
+        let next = (i + 1) % items.len();
+        Ok(cons(items[i].clone(), cycle(items, next)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mal_core::tests::{assert_evals_to, assert_fails};
+
+    #[test]
+    fn map_and_filter() {
+        assert_evals_to("(map inc [1 2])", "(2 3)");
+        assert_evals_to(r#"(map str "ab")"#, r#"("a" "b")"#);
+        assert_evals_to("(map first {:a 1})", "(:a)");
+        assert_evals_to("(map + [1 2] '(10 20 30))", "(11 22)");
+        assert_evals_to("(map inc nil)", "()");
+        assert_evals_to("(filter even? '(1 2 3 4))", "(2 4)");
+        assert_evals_to("(remove even? [1 2])", "(1)");
+        assert_evals_to("(filter (fn* [e] (> (nth e 1) 1)) {:a 1 :b 2})", "([:b 2])");
+        assert_evals_to(r#"(remove (fn* [c] (= c \a)) "abc")"#, r"(\b \c)");
+        assert_evals_to("(filter even? nil)", "()");
+    }
+
+    #[test]
+    fn mapcat_and_keep() {
+        assert_evals_to("(mapcat reverse [[1 2] [3]])", "(2 1 3)");
+        assert_evals_to(r#"(mapcat list "ab")"#, r"(\a \b)");
+        assert_evals_to("(mapcat (fn* [x] x) {:a 1})", "(:a 1)");
+        assert_evals_to("(mapcat list nil)", "()");
+        assert_evals_to(
+            "(keep (fn* [x] (if (even? x) (* x x))) '(1 2 3 4))",
+            "(4 16)",
+        );
+        assert_evals_to(r#"(keep seq ["" "a"])"#, r"((\a))");
+        assert_evals_to(r#"(keep (fn* [c] (if (= c \a) c)) "aba")"#, r"(\a \a)");
+        assert_evals_to("(keep last {:a 1})", "(1)");
+        assert_evals_to("(keep seq nil)", "()");
+    }
+
+    #[test]
+    fn interleave_and_distinct() {
+        assert_evals_to(r#"(interleave [1 2 3] "ab")"#, r"(1 \a 2 \b)");
+        assert_evals_to("(interleave '(1) {:a 1})", "(1 [:a 1])");
+        assert_evals_to("(interleave nil [1])", "()");
+        assert_evals_to("(distinct [1 2 1 3 2])", "(1 2 3)");
+        assert_evals_to("(distinct '(1 1))", "(1)");
+        assert_evals_to(r#"(distinct "abca")"#, r"(\a \b \c)");
+        assert_evals_to("(distinct {:a 1})", "([:a 1])");
+        assert_evals_to("(distinct nil)", "()");
+    }
+
+    #[test]
+    fn partition() {
+        assert_evals_to("(partition 2 [1 2 3 4 5])", "((1 2) (3 4))");
+        assert_evals_to("(partition 2 '(1 2))", "((1 2))");
+        assert_evals_to(r#"(partition 2 1 "abc")"#, r"((\a \b) (\b \c))");
+        assert_evals_to("(partition 1 {:a 1})", "(([:a 1]))");
+        assert_evals_to("(partition 2 nil)", "()");
+    }
+
+    #[test]
+    fn take_and_drop() {
+        assert_evals_to(r#"(take 2 "abc")"#, r"(\a \b)");
+        assert_evals_to("(take 5 [1])", "(1)");
+        assert_evals_to("(take 1 '(1 2))", "(1)");
+        assert_evals_to("(take 1 {:a 1})", "([:a 1])");
+        assert_evals_to("(drop 1 {:a 1})", "()");
+        assert_evals_to(r#"(drop 2 "abc")"#, r"(\c)");
+        assert_evals_to("(drop 2 nil)", "()");
+        assert_evals_to("(take-while even? [2 4 5 6])", "(2 4)");
+        assert_evals_to("(take-while even? nil)", "()");
+    }
+
+    #[test]
+    fn generators() {
+        assert_evals_to("(range 3)", "(0 1 2)");
+        assert_evals_to("(range 1 4)", "(1 2 3)");
+        assert_evals_to("(range 0 10 3)", "(0 3 6 9)");
+        assert_evals_to("(range 3 0 -1)", "(3 2 1)");
+        assert_evals_to("(take 3 (range))", "(0 1 2)");
+        assert_evals_to("(take 3 (iterate inc 0))", "(0 1 2)");
+        assert_evals_to("(repeat 2 :x)", "(:x :x)");
+        assert_evals_to("(take 2 (repeat 1))", "(1 1)");
+        assert_evals_to(r#"(take 5 (cycle "ab"))"#, r"(\a \b \a \b \a)");
+        assert_evals_to("(take 2 (cycle {:a 1}))", "([:a 1] [:a 1])");
+        assert_evals_to("(cycle nil)", "()");
+        assert_fails("(map inc 1)");
+    }
+
+    #[test]
+    fn errors_on_infinite_seqs() {
+        assert_evals_to("(try* (+ (range) 1) (catch* e 1))", "1");
+        assert_evals_to("(try* (subs (range) 1) (catch* e 1))", "1");
+        assert_evals_to("(take 2 (conj (range) 1))", "(1 0)");
+        // error messages show the elements realized so far
+        assert_evals_to(
+            "(let* [r (range)] (doall (take 3 r)) (try* (+ r 1) (catch* e e)))",
+            r#""expected number, got (0 1 2 ...)""#,
+        );
+        assert_evals_to(
+            "(try* (+ [(range)] 1) (catch* e e))",
+            r#""expected number, got [(...)]""#,
+        );
+    }
+
+    #[test]
+    fn lazy_seqs_of_any_seqable() {
+        assert_evals_to(r#"(lazy-seq "ab")"#, r"(\a \b)");
+        assert_evals_to("(lazy-seq {:a 1})", "([:a 1])");
+        assert_evals_to("(lazy-seq #{1})", "(1)");
+        assert_evals_to("(lazy-seq (lazy-seq [1 2]))", "(1 2)");
+        assert_fails("(lazy-seq 1)");
+    }
+
+    #[test]
+    fn errors_in_lazy_seqs() {
+        let failing = r#"(lazy-seq (cons 1 (lazy-seq (throw "x"))))"#;
+        for form in &["(pr-str {})", "(str {})", "(join [{}])", "(= {} [1])"] {
+            assert_fails(&form.replace("{}", failing));
+        }
+        // sequences that fail to realize are only equal to themselves
+        assert_evals_to(
+            r#"(let* [s (lazy-seq (throw "x"))] (count (hash-set s s (lazy-seq (throw "x")) ())))"#,
+            "3",
+        );
+    }
+}
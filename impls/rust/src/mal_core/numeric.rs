@@ -0,0 +1,447 @@
+use std::{cmp::Ordering, convert::TryFrom};
+
+use num_bigint::BigInt;
+use num_traits::{FromPrimitive, Signed, Zero};
+
+use super::{ensure_arity, make_fn_val};
+use crate::{
+    env::Env,
+    number::Int,
+    runtime_errors::{self, RuntimeResult},
+    value::{NumberPair, Value},
+};
+
+type Args = std::vec::IntoIter<Value>;
+
+pub fn init_env(env: &mut Env) {
+    env.set(
+        "+",
+        make_fn_val(|args, _| fold_numbers(args, Value::Number(Int::Small(0)), |a, b| a + b)),
+    );
+    env.set(
+        "*",
+        make_fn_val(|args, _| fold_numbers(args, Value::Number(Int::Small(1)), |a, b| a * b)),
+    );
+    env.set(
+        "-",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "-", 1..)?;
+            let first = args.next().unwrap();
+            if args.len() == 0 {
+                return -first;
+            }
+            args.try_fold(first, |acc, n| acc - n)
+        }),
+    );
+    env.set(
+        "/",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "/", 1..)?;
+            let first = args.next().unwrap();
+            if args.len() == 0 {
+                return Value::Number(Int::Small(1)) / first;
+            }
+            args.try_fold(first, |acc, n| acc / n)
+        }),
+    );
+
+    env.set(
+        "<",
+        make_fn_val(|args, _| compare_chain(args, "<", |o| o == Ordering::Less)),
+    );
+    env.set(
+        ">",
+        make_fn_val(|args, _| compare_chain(args, ">", |o| o == Ordering::Greater)),
+    );
+    env.set(
+        "<=",
+        make_fn_val(|args, _| compare_chain(args, "<=", |o| o != Ordering::Greater)),
+    );
+    env.set(
+        ">=",
+        make_fn_val(|args, _| compare_chain(args, ">=", |o| o != Ordering::Less)),
+    );
+    env.set(
+        "==",
+        make_fn_val(|args, _| compare_chain(args, "==", |o| o == Ordering::Equal)),
+    );
+
+    env.set(
+        "inc",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "inc", 1..=1)?;
+            args.next().unwrap() + Value::Number(Int::Small(1))
+        }),
+    );
+    env.set(
+        "dec",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "dec", 1..=1)?;
+            args.next().unwrap() - Value::Number(Int::Small(1))
+        }),
+    );
+    env.set(
+        "quot",
+        make_fn_val(|args, _| integer_division(args, "quot", Division::Quot)),
+    );
+    env.set(
+        "rem",
+        make_fn_val(|args, _| integer_division(args, "rem", Division::Rem)),
+    );
+    env.set(
+        "mod",
+        make_fn_val(|args, _| integer_division(args, "mod", Division::Mod)),
+    );
+    env.set(
+        "abs",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "abs", 1..=1)?;
+            match args.next().unwrap() {
+                Value::Number(n) => Ok(Value::Number(n.abs())),
+                Value::Ratio(r) => Ok(Value::from(r.abs())),
+                Value::Float(f) => Ok(Value::Float(f.abs())),
+                v => Err(runtime_errors::not_a("number", &v)),
+            }
+        }),
+    );
+    env.set(
+        "min",
+        make_fn_val(|args, _| extremum(args, "min", Ordering::Less)),
+    );
+    env.set(
+        "max",
+        make_fn_val(|args, _| extremum(args, "max", Ordering::Greater)),
+    );
+    env.set(
+        "pow",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "pow", 2..=2)?;
+            pow(args.next().unwrap(), args.next().unwrap())
+        }),
+    );
+    env.set(
+        "sqrt",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "sqrt", 1..=1)?;
+            Ok(Value::Float(try_as_f64(&args.next().unwrap())?.sqrt()))
+        }),
+    );
+    env.set(
+        "floor",
+        make_fn_val(|args, _| round(args, "floor", |r| r.floor(), f64::floor)),
+    );
+    env.set(
+        "ceil",
+        make_fn_val(|args, _| round(args, "ceil", |r| r.ceil(), f64::ceil)),
+    );
+    env.set(
+        "round",
+        make_fn_val(|args, _| round(args, "round", |r| r.round(), f64::round)),
+    );
+    // truncates numbers towards zero and converts characters to their code point
+    env.set(
+        "int",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "int", 1..=1)?;
+            match args.next().unwrap() {
+                n @ Value::Number(_) => Ok(n),
+                Value::Ratio(r) => Ok(Value::from(r.trunc())),
+                Value::Float(f) => BigInt::from_f64(f.trunc())
+                    .map(|i| Value::Number(Int::from(i)))
+                    .ok_or_else(|| runtime_errors::not_a("finite number", &Value::Float(f))),
+                Value::Char(c) => Ok(Value::Number(Int::Small(c as i64))),
+                v => Err(runtime_errors::not_a("number or character", &v)),
+            }
+        }),
+    );
+
+    env.set(
+        "number?",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "number?", 1..=1)?;
+            Ok(Value::Bool(args.next().unwrap().is_number()))
+        }),
+    );
+    env.set(
+        "zero?",
+        make_fn_val(|args, _| sign_is(args, "zero?", Ordering::Equal)),
+    );
+    env.set(
+        "pos?",
+        make_fn_val(|args, _| sign_is(args, "pos?", Ordering::Greater)),
+    );
+    env.set(
+        "neg?",
+        make_fn_val(|args, _| sign_is(args, "neg?", Ordering::Less)),
+    );
+    env.set(
+        "even?",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "even?", 1..=1)?;
+            Ok(Value::Bool(args.next().unwrap().try_into_int()?.is_even()))
+        }),
+    );
+    env.set(
+        "odd?",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "odd?", 1..=1)?;
+            Ok(Value::Bool(!args.next().unwrap().try_into_int()?.is_even()))
+        }),
+    );
+
+    env.set(
+        "bit-and",
+        make_fn_val(|args, _| fold_ints(args, "bit-and", |a, b| a & b)),
+    );
+    env.set(
+        "bit-or",
+        make_fn_val(|args, _| fold_ints(args, "bit-or", |a, b| a | b)),
+    );
+    env.set(
+        "bit-xor",
+        make_fn_val(|args, _| fold_ints(args, "bit-xor", |a, b| a ^ b)),
+    );
+    env.set(
+        "bit-shift-left",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "bit-shift-left", 2..=2)?;
+            let n = args.next().unwrap().try_into_int()?;
+            let bits = shift_amount(args.next().unwrap())?;
+            if bits > MAX_LEFT_SHIFT {
+                return Err(runtime_errors::error_to_string(format!(
+                    "bit-shift-left: shift amount {} is larger than {}",
+                    bits, MAX_LEFT_SHIFT
+                )));
+            }
+            Ok(Value::Number(n.shl(bits)))
+        }),
+    );
+    env.set(
+        "bit-shift-right",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "bit-shift-right", 2..=2)?;
+            let n = args.next().unwrap().try_into_int()?;
+            Ok(Value::Number(n.shr(shift_amount(args.next().unwrap())?)))
+        }),
+    );
+}
+
+fn ensure_number(v: Value) -> RuntimeResult<Value> {
+    if v.is_number() {
+        Ok(v)
+    } else {
+        Err(runtime_errors::not_a("number", &v))
+    }
+}
+
+fn try_as_f64(v: &Value) -> RuntimeResult<f64> {
+    v.try_as_f64()
+        .ok_or_else(|| runtime_errors::not_a("number", v))
+}
+
+/// Folds the arguments from left to right, returning `identity` if there are none.
+fn fold_numbers(
+    mut args: Args,
+    identity: Value,
+    op: fn(Value, Value) -> RuntimeResult<Value>,
+) -> RuntimeResult<Value> {
+    match args.next() {
+        Some(first) => args.try_fold(ensure_number(first)?, op),
+        None => Ok(identity),
+    }
+}
+
+fn fold_ints(mut args: Args, name: &str, op: fn(Int, Int) -> Int) -> RuntimeResult<Value> {
+    ensure_arity(&args, name, 2..)?;
+    let first = args.next().unwrap().try_into_int()?;
+    args.try_fold(first, |acc, n| Ok(op(acc, n.try_into_int()?)))
+        .map(Value::Number)
+}
+
+/// Checks that `matches` holds for every pair of adjacent arguments.
+fn compare_chain(args: Args, name: &str, matches: fn(Ordering) -> bool) -> RuntimeResult<Value> {
+    ensure_arity(&args, name, 1..)?;
+    let args = args.map(ensure_number).collect::<RuntimeResult<Vec<_>>>()?;
+    Ok(Value::Bool(args.windows(2).all(|pair| {
+        pair[0].numeric_cmp(&pair[1]).is_some_and(matches)
+    })))
+}
+
+fn is_nan(v: &Value) -> bool {
+    matches!(v, Value::Float(f) if f.is_nan())
+}
+
+fn extremum(mut args: Args, name: &str, wanted: Ordering) -> RuntimeResult<Value> {
+    ensure_arity(&args, name, 1..)?;
+    let first = ensure_number(args.next().unwrap())?;
+    args.try_fold(first, |acc, n| {
+        let n = ensure_number(n)?;
+        // NaN is contagious, like in other arithmetic
+        if !is_nan(&acc) && (is_nan(&n) || n.numeric_cmp(&acc) == Some(wanted)) {
+            Ok(n)
+        } else {
+            Ok(acc)
+        }
+    })
+}
+
+fn sign_is(mut args: Args, name: &str, sign: Ordering) -> RuntimeResult<Value> {
+    ensure_arity(&args, name, 1..=1)?;
+    let n = ensure_number(args.next().unwrap())?;
+    Ok(Value::Bool(
+        n.numeric_cmp(&Value::Number(Int::Small(0))) == Some(sign),
+    ))
+}
+
+enum Division {
+    Quot,
+    Rem,
+    Mod,
+}
+
+/// `quot` truncates towards zero, `rem` is the matching remainder (with the sign of the dividend)
+/// and `mod` is the remainder of flooring division (with the sign of the divisor).
+/// Ratios and floats follow the same rules as integers.
+fn integer_division(mut args: Args, name: &str, kind: Division) -> RuntimeResult<Value> {
+    ensure_arity(&args, name, 2..=2)?;
+    let division_by_zero = || runtime_errors::error_to_string("division by zero");
+    match NumberPair::coerce(&args.next().unwrap(), &args.next().unwrap())? {
+        NumberPair::Ints(a, b) => match kind {
+            Division::Quot => a.quot(b),
+            Division::Rem => a.rem(b),
+            Division::Mod => a.modulo(b),
+        }
+        .map(Value::Number)
+        .ok_or_else(division_by_zero),
+        NumberPair::Ratios(a, b) => {
+            if b.is_zero() {
+                return Err(division_by_zero());
+            }
+            let quotient = &a / &b;
+            Ok(match kind {
+                Division::Quot => Value::from(quotient.trunc()),
+                Division::Rem => Value::from(a - b * quotient.trunc()),
+                Division::Mod => Value::from(a - b * quotient.floor()),
+            })
+        }
+        NumberPair::Floats(a, b) => Ok(Value::Float(match kind {
+            Division::Quot => (a / b).trunc(),
+            Division::Rem => a % b,
+            Division::Mod => {
+                let r = a % b;
+                if r != 0.0 && (r < 0.0) != (b < 0.0) {
+                    r + b
+                } else {
+                    r
+                }
+            }
+        })),
+    }
+}
+
+/// Exact numbers are raised exactly if the exponent is an integer, everything else yields a float.
+fn pow(base: Value, exp: Value) -> RuntimeResult<Value> {
+    let e = match (&base, &exp) {
+        (Value::Number(_) | Value::Ratio(_), Value::Number(e)) => exact_exponent(&base, e)?,
+        _ => return Ok(Value::Float(try_as_f64(&base)?.powf(try_as_f64(&exp)?))),
+    };
+    match base {
+        Value::Number(n) if e >= 0 => Ok(Value::Number(n.pow(e as u32))),
+        base => {
+            let base = match base {
+                Value::Number(n) => n.to_ratio(),
+                Value::Ratio(r) => r.as_ref().clone(),
+                _ => unreachable!(),
+            };
+            if base.is_zero() && e < 0 {
+                return Err(runtime_errors::error_to_string("division by zero"));
+            }
+            Ok(Value::from(base.pow(e)))
+        }
+    }
+}
+
+/// The exponent of an exact power. Like the results of left shifts, exact powers are
+/// limited to about `MAX_LEFT_SHIFT` bits to keep them allocatable.
+fn exact_exponent(base: &Value, exp: &Int) -> RuntimeResult<i32> {
+    let too_large = || {
+        runtime_errors::error_to_string(format!(
+            "pow: exponent {} is too large for an exact result",
+            exp
+        ))
+    };
+    let e = match exp {
+        Int::Small(e) => i32::try_from(*e).map_err(|_| too_large())?,
+        Int::Big(_) => return Err(too_large()),
+    };
+    let bits = match base {
+        Value::Number(n) => n.to_big().bits(),
+        Value::Ratio(r) => r.numer().bits().max(r.denom().bits()),
+        _ => unreachable!(),
+    };
+    // the result has about (bits - 1) * |e| bits, so powers of 0, 1 and -1 stay small
+    if bits.saturating_sub(1) * u64::from(e.unsigned_abs()) > MAX_LEFT_SHIFT as u64 {
+        return Err(too_large());
+    }
+    Ok(e)
+}
+
+/// Exact numbers are rounded to integers, floats stay floats.
+fn round(
+    mut args: Args,
+    name: &str,
+    ratio: fn(&num_rational::BigRational) -> num_rational::BigRational,
+    float: fn(f64) -> f64,
+) -> RuntimeResult<Value> {
+    ensure_arity(&args, name, 1..=1)?;
+    match args.next().unwrap() {
+        n @ Value::Number(_) => Ok(n),
+        Value::Ratio(r) => Ok(Value::from(ratio(&r))),
+        Value::Float(f) => Ok(Value::Float(float(f))),
+        v => Err(runtime_errors::not_a("number", &v)),
+    }
+}
+
+/// Results of left shifts grow with the shift amount, so it is limited to keep them
+/// allocatable. Right shifts only make numbers smaller.
+const MAX_LEFT_SHIFT: usize = 1 << 24;
+
+fn shift_amount(v: Value) -> RuntimeResult<usize> {
+    match v {
+        Value::Number(n) if !n.is_negative() => n
+            .to_usize()
+            .ok_or_else(|| runtime_errors::error_to_string("shift amount too large")),
+        v => Err(runtime_errors::not_a("non-negative integer", &v)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mal_core::tests::{assert_evals_to, assert_fails};
+
+    #[test]
+    fn shifts() {
+        assert_evals_to("(bit-shift-left 1 64)", "18446744073709551616");
+        assert_evals_to("(bit-shift-right -5 100000000000000)", "-1");
+        assert_fails("(bit-shift-left 1 100000000000000)");
+        assert_fails("(bit-shift-left 1 16777217)");
+        assert_fails("(bit-shift-left 1 -1)");
+    }
+
+    #[test]
+    fn powers() {
+        assert_evals_to("(pow 2 10)", "1024");
+        assert_evals_to("(pow 2 64)", "18446744073709551616");
+        assert_evals_to("(pow 2 -2)", "1/4");
+        assert_evals_to("(pow 2/3 2)", "4/9");
+        assert_evals_to("(pow 4 0.5)", "2.0");
+        assert_evals_to("(pow 2.0 100000000000)", "##Inf");
+        assert_evals_to("(pow -1 1000000001)", "-1");
+        assert_fails("(pow 0 -1)");
+        // exact results are limited like left shifts
+        assert_fails("(pow 2 100000000000)");
+        assert_fails("(pow 2 16777217)");
+        assert_fails("(pow 1/2 -16777217)");
+        assert_fails("(pow 2 100000000000000000000)");
+    }
+}
@@ -0,0 +1,113 @@
+use std::rc::Rc;
+
+use regex::Captures;
+
+use super::{ensure_arity, make_fn_val};
+use crate::{
+    env::Env,
+    pattern::Pattern,
+    runtime_errors::{self, RuntimeResult},
+    value::Value,
+};
+
+type Args = std::vec::IntoIter<Value>;
+
+pub fn init_env(env: &mut Env) {
+    env.set(
+        "re-pattern",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "re-pattern", 1..=1)?;
+            match args.next().unwrap() {
+                Value::Regex(p) => Ok(Value::Regex(p)),
+                Value::String(s) => Ok(Value::Regex(Rc::new(Pattern::new(&s).map_err(|e| {
+                    runtime_errors::error_to_string(format!("invalid regex: {}", e))
+                })?))),
+                v => Err(runtime_errors::not_a("string or regex", &v)),
+            }
+        }),
+    );
+    env.set(
+        "regex?",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "regex?", 1..=1)?;
+            Ok(Value::Bool(matches!(args.next().unwrap(), Value::Regex(_))))
+        }),
+    );
+    env.set(
+        "re-find",
+        make_fn_val(|args, _| {
+            first_match(args, "re-find", |p, s| {
+                p.regex().captures(s).map(match_value)
+            })
+        }),
+    );
+    env.set(
+        "re-matches",
+        make_fn_val(|args, _| {
+            first_match(args, "re-matches", |p, s| {
+                p.anchored().captures(s).map(match_value)
+            })
+        }),
+    );
+    env.set(
+        "re-seq",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "re-seq", 2..=2)?;
+            let pattern = next_pattern(&mut args)?;
+            let s = args.next().unwrap().try_into_string()?;
+            let matches: Vec<Value> = pattern.regex().captures_iter(&s).map(match_value).collect();
+            if matches.is_empty() {
+                Ok(Value::Nil)
+            } else {
+                Ok(Value::List(matches, Box::new(Value::Nil)))
+            }
+        }),
+    );
+    // There are no stateful matchers, so unlike in Clojure `re-groups` takes the
+    // pattern and the string and always returns the groups of the first match.
+    env.set(
+        "re-groups",
+        make_fn_val(|args, _| {
+            first_match(args, "re-groups", |p, s| {
+                p.regex().captures(s).map(|caps| groups_vec(&caps))
+            })
+        }),
+    );
+}
+
+pub(super) fn next_pattern(args: &mut Args) -> RuntimeResult<Rc<Pattern>> {
+    match args.next().unwrap() {
+        Value::Regex(p) => Ok(p),
+        v => Err(runtime_errors::not_a("regex", &v)),
+    }
+}
+
+/// The value of a match: the matched string if the pattern has no groups,
+/// otherwise a vector of the whole match followed by all groups (nil if a group didn't match).
+pub(super) fn match_value(caps: Captures) -> Value {
+    if caps.len() == 1 {
+        Value::String(caps[0].to_owned())
+    } else {
+        groups_vec(&caps)
+    }
+}
+
+fn groups_vec(caps: &Captures) -> Value {
+    Value::Vec(
+        caps.iter()
+            .map(|group| group.map_or(Value::Nil, |m| Value::String(m.as_str().to_owned())))
+            .collect(),
+        Box::new(Value::Nil),
+    )
+}
+
+fn first_match(
+    mut args: Args,
+    name: &str,
+    find: fn(&Pattern, &str) -> Option<Value>,
+) -> RuntimeResult<Value> {
+    ensure_arity(&args, name, 2..=2)?;
+    let pattern = next_pattern(&mut args)?;
+    let s = args.next().unwrap().try_into_string()?;
+    Ok(find(&pattern, &s).unwrap_or(Value::Nil))
+}
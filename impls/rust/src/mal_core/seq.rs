@@ -0,0 +1,251 @@
+use super::{conj, ensure_arity, is_truthy, make_fn_val};
+use crate::{
+    env::Env,
+    eval_fn_no_tco,
+    hash_map::PersistentHashMap,
+    value::{SeqIter, Value},
+    vector::PersistentVector,
+};
+
+pub fn init_env(env: &mut Env) {
+    env.set(
+        "reduce",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "reduce", 2..=3)?;
+            let f = args.next().unwrap();
+            let init = if args.len() == 2 { args.next() } else { None };
+            let mut coll = SeqIter::new(args.next().unwrap())?;
+            let mut acc = match init {
+                Some(init) => init,
+                None => match coll.next() {
+                    Some(first) => first?,
+                    // like in Clojure, reducing nothing calls `f` without arguments
+                    None => return eval_fn_no_tco(f, vec![], env),
+                },
+            };
+            for v in coll {
+                acc = eval_fn_no_tco(f.clone(), vec![acc, v?], env.clone())?;
+            }
+            Ok(acc)
+        }),
+    );
+    env.set(
+        "reverse",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "reverse", 1..=1)?;
+            let coll = args.next().unwrap();
+            // unlike in Clojure, reversing a string gives a string
+            if let Value::String(s) = &coll {
+                return Ok(Value::String(s.chars().rev().collect()));
+            }
+            let mut elements = coll.seq_elements()?;
+            elements.reverse();
+            Ok(Value::List(elements, Box::new(Value::Nil)))
+        }),
+    );
+    env.set(
+        "last",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "last", 1..=1)?;
+            let mut last = Value::Nil;
+            for v in SeqIter::new(args.next().unwrap())? {
+                last = v?;
+            }
+            Ok(last)
+        }),
+    );
+    env.set(
+        "butlast",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "butlast", 1..=1)?;
+            let mut elements = args.next().unwrap().seq_elements()?;
+            elements.pop();
+            if elements.is_empty() {
+                Ok(Value::Nil)
+            } else {
+                Ok(Value::List(elements, Box::new(Value::Nil)))
+            }
+        }),
+    );
+    env.set(
+        "some",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "some", 2..=2)?;
+            let pred = args.next().unwrap();
+            for v in SeqIter::new(args.next().unwrap())? {
+                let result = eval_fn_no_tco(pred.clone(), vec![v?], env.clone())?;
+                if is_truthy(&result) {
+                    return Ok(result);
+                }
+            }
+            Ok(Value::Nil)
+        }),
+    );
+    env.set(
+        "every?",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "every?", 2..=2)?;
+            let pred = args.next().unwrap();
+            for v in SeqIter::new(args.next().unwrap())? {
+                if !is_truthy(&eval_fn_no_tco(pred.clone(), vec![v?], env.clone())?) {
+                    return Ok(Value::Bool(false));
+                }
+            }
+            Ok(Value::Bool(true))
+        }),
+    );
+    env.set(
+        "into",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "into", 0..=2)?;
+            let to = args
+                .next()
+                .unwrap_or_else(|| Value::Vec(PersistentVector::new(), Box::new(Value::Nil)));
+            match args.next() {
+                Some(from) => conj(to, from.seq_elements()?.into_iter(), &env),
+                None => Ok(to),
+            }
+        }),
+    );
+    env.set(
+        "group-by",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "group-by", 2..=2)?;
+            let f = args.next().unwrap();
+            let mut groups = PersistentHashMap::<Value, PersistentVector<Value>>::new();
+            for v in SeqIter::new(args.next().unwrap())? {
+                let v = v?;
+                let key = eval_fn_no_tco(f.clone(), vec![v.clone()], env.clone())?;
+                let mut group = groups.remove(&key).unwrap_or_default();
+                group.push(v);
+                groups.insert(key, group);
+            }
+            Ok(Value::Map(
+                groups
+                    .iter()
+                    .map(|(k, group)| (k.clone(), Value::Vec(group.clone(), Box::new(Value::Nil))))
+                    .collect(),
+                Box::new(Value::Nil),
+            ))
+        }),
+    );
+    env.set(
+        "frequencies",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "frequencies", 1..=1)?;
+            let mut counts = PersistentHashMap::<Value, usize>::new();
+            for v in SeqIter::new(args.next().unwrap())? {
+                let v = v?;
+                let count = counts.get(&v).copied().unwrap_or(0);
+                counts.insert(v, count + 1);
+            }
+            Ok(Value::Map(
+                counts
+                    .iter()
+                    .map(|(k, count)| (k.clone(), Value::Number((*count).into())))
+                    .collect(),
+                Box::new(Value::Nil),
+            ))
+        }),
+    );
+    env.set(
+        "zipmap",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "zipmap", 2..=2)?;
+            let keys = SeqIter::new(args.next().unwrap())?;
+            let mut vals = SeqIter::new(args.next().unwrap())?;
+            let mut map = PersistentHashMap::new();
+            for k in keys {
+                match vals.next() {
+                    Some(v) => map.insert(k?, v?),
+                    None => break,
+                }
+            }
+            Ok(Value::Map(map, Box::new(Value::Nil)))
+        }),
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mal_core::tests::{assert_evals_to, assert_fails};
+
+    #[test]
+    fn reverse() {
+        assert_evals_to("(reverse \"héllo\")", "\"olléh\"");
+        assert_evals_to("(reverse \"\")", "\"\"");
+        assert_evals_to("(reverse [1 2 3])", "(3 2 1)");
+        assert_evals_to("(reverse nil)", "()");
+    }
+
+    #[test]
+    fn reduce() {
+        assert_evals_to("(reduce + [1 2 3])", "6");
+        assert_evals_to("(reduce + 10 '(1 2))", "13");
+        assert_evals_to("(reduce + nil)", "0");
+        assert_evals_to("(reduce + 5 nil)", "5");
+        assert_evals_to("(reduce (fn* [a e] (+ a (nth e 1))) 0 {:a 1 :b 2})", "3");
+        assert_evals_to(r#"(reduce str "" "abc")"#, r#""abc""#);
+        assert_fails("(reduce +)");
+    }
+
+    #[test]
+    fn last_and_butlast() {
+        assert_evals_to("(last [1 2])", "2");
+        assert_evals_to("(last nil)", "nil");
+        assert_evals_to(r#"(last "ab")"#, r"\b");
+        assert_evals_to("(last {:a 1})", "[:a 1]");
+        assert_evals_to("(butlast '(1 2 3))", "(1 2)");
+        assert_evals_to("(butlast [1])", "nil");
+        assert_evals_to(r#"(butlast "abc")"#, r"(\a \b)");
+    }
+
+    #[test]
+    fn some_and_every() {
+        assert_evals_to("(some even? [1 2])", "true");
+        assert_evals_to("(some even? nil)", "nil");
+        assert_evals_to(r#"(some (fn* [c] (= c \b)) "abc")"#, "true");
+        assert_evals_to("(some (fn* [e] (= (first e) :a)) {:a 1})", "true");
+        assert_evals_to("(every? odd? '(1 3))", "true");
+        assert_evals_to("(every? odd? nil)", "true");
+        assert_evals_to("(every? vector? {:a 1})", "true");
+        assert_evals_to(r#"(every? char? "ab")"#, "true");
+    }
+
+    #[test]
+    fn into() {
+        assert_evals_to("(into [] '(1 2))", "[1 2]");
+        assert_evals_to("(into '() [1 2])", "(2 1)");
+        assert_evals_to("(into {} [[:a 1]])", "{:a 1}");
+        assert_evals_to("(into [] {:a 1})", "[[:a 1]]");
+        assert_evals_to(r#"(into #{} "aba")"#, r"#{\a \b}");
+        assert_evals_to("(into [1] nil)", "[1]");
+        assert_evals_to("(into nil [1])", "(1)");
+    }
+
+    #[test]
+    fn grouping() {
+        assert_evals_to(
+            "(= (group-by even? [1 2 3]) {true [2] false [1 3]})",
+            "true",
+        );
+        assert_evals_to(
+            r#"(= (group-by (fn* [x] x) "aab") {\a [\a \a] \b [\b]})"#,
+            "true",
+        );
+        assert_evals_to("(group-by count nil)", "{}");
+        assert_evals_to("(= (group-by count {:a 1}) {2 [[:a 1]]})", "true");
+        assert_evals_to(r#"(= (frequencies "abca") {\a 2 \b 1 \c 1})"#, "true");
+        assert_evals_to("(= (frequencies '(1 1)) {1 2})", "true");
+        assert_evals_to("(frequencies nil)", "{}");
+        assert_evals_to("(frequencies {:a 1})", "{[:a 1] 1}");
+    }
+
+    #[test]
+    fn zipmap() {
+        assert_evals_to("(= (zipmap [:a :b] '(1 2 3)) {:a 1 :b 2})", "true");
+        assert_evals_to(r#"(= (zipmap "ab" [1 2]) {\a 1 \b 2})"#, "true");
+        assert_evals_to("(zipmap {:a 1} [1])", "{[:a 1] 1}");
+        assert_evals_to("(zipmap nil [1])", "{}");
+    }
+}
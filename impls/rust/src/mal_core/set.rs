@@ -0,0 +1,141 @@
+use super::{ensure_arity, make_fn_val};
+use crate::{
+    env::Env,
+    hash_set::PersistentHashSet,
+    runtime_errors::{self, RuntimeResult},
+    value::Value,
+};
+
+type Args = std::vec::IntoIter<Value>;
+
+pub fn init_env(env: &mut Env) {
+    env.set(
+        "hash-set",
+        make_fn_val(|args, _| Ok(set_value(args.collect()))),
+    );
+    env.set(
+        "set",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "set", 1..=1)?;
+            let set = match args.next().unwrap() {
+                Value::Set(set, _) => set,
+                coll => coll.seq_elements()?.into_iter().collect(),
+            };
+            Ok(set_value(set))
+        }),
+    );
+    env.set(
+        "set?",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "set?", 1..=1)?;
+            Ok(Value::Bool(matches!(
+                args.next().unwrap(),
+                Value::Set(_, _) | Value::SortedSet(_, _)
+            )))
+        }),
+    );
+    env.set(
+        "disj",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "disj", 1..)?;
+            let mut set = match args.next().unwrap() {
+                Value::Nil => return Ok(Value::Nil),
+                Value::SortedSet(mut set, _) => {
+                    for v in args {
+                        set.remove(&v, &env)?;
+                    }
+                    return Ok(Value::SortedSet(set, Box::new(Value::Nil)));
+                }
+                set => into_set(set)?,
+            };
+            for v in args {
+                set.remove(&v);
+            }
+            Ok(set_value(set))
+        }),
+    );
+    env.set(
+        "union",
+        make_fn_val(|args, _| {
+            let mut sets = sets(args)?;
+            // add the smaller sets to the largest one
+            let largest = (0..sets.len()).max_by_key(|&i| sets[i].len());
+            let mut union = match largest {
+                Some(i) => sets.swap_remove(i),
+                None => PersistentHashSet::new(),
+            };
+            for set in sets {
+                union.extend(set.iter().cloned());
+            }
+            Ok(set_value(union))
+        }),
+    );
+    env.set(
+        "intersection",
+        make_fn_val(|args, _| {
+            ensure_arity(&args, "intersection", 1..)?;
+            let mut sets = sets(args)?;
+            let smallest = (0..sets.len()).min_by_key(|&i| sets[i].len()).unwrap();
+            let smallest = sets.swap_remove(smallest);
+            Ok(set_value(
+                smallest
+                    .iter()
+                    .filter(|v| sets.iter().all(|set| set.contains(*v)))
+                    .cloned()
+                    .collect(),
+            ))
+        }),
+    );
+    env.set(
+        "difference",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "difference", 1..)?;
+            let mut difference = into_set(args.next().unwrap())?;
+            for set in sets(args)? {
+                for v in set.iter() {
+                    difference.remove(v);
+                }
+            }
+            Ok(set_value(difference))
+        }),
+    );
+    env.set(
+        "subset?",
+        make_fn_val(|args, _| {
+            let (a, b) = two_sets(args, "subset?")?;
+            Ok(Value::Bool(a.is_subset(&b)))
+        }),
+    );
+    env.set(
+        "superset?",
+        make_fn_val(|args, _| {
+            let (a, b) = two_sets(args, "superset?")?;
+            Ok(Value::Bool(b.is_subset(&a)))
+        }),
+    );
+}
+
+fn set_value(set: PersistentHashSet<Value>) -> Value {
+    Value::Set(set, Box::new(Value::Nil))
+}
+
+fn into_set(v: Value) -> RuntimeResult<PersistentHashSet<Value>> {
+    match v {
+        Value::Set(set, _) => Ok(set),
+        Value::SortedSet(set, _) => Ok(set.entries.iter().map(|(v, _)| v.clone()).collect()),
+        v => Err(runtime_errors::not_a("set", &v)),
+    }
+}
+
+fn sets(args: Args) -> RuntimeResult<Vec<PersistentHashSet<Value>>> {
+    args.map(into_set).collect()
+}
+
+fn two_sets(
+    mut args: Args,
+    name: &str,
+) -> RuntimeResult<(PersistentHashSet<Value>, PersistentHashSet<Value>)> {
+    ensure_arity(&args, name, 2..=2)?;
+    let a = into_set(args.next().unwrap())?;
+    Ok((a, into_set(args.next().unwrap())?))
+}
@@ -0,0 +1,227 @@
+use std::{cell::RefCell, cmp::Ordering, rc::Rc};
+
+use super::{compare::call_comparator, ensure_arity, ensure_even_args, make_fn_val};
+use crate::{
+    env::Env,
+    eval_fn_no_tco,
+    number::Int,
+    runtime_errors::{self, RuntimeResult},
+    sorted_map::{self, PersistentSortedMap},
+    value::{Sorted, Value},
+};
+
+type Args = std::vec::IntoIter<Value>;
+
+pub fn init_env(env: &mut Env) {
+    env.set(
+        "sorted-map",
+        make_fn_val(|args, env| sorted_map(None, args, &env)),
+    );
+    env.set(
+        "sorted-map-by",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "sorted-map-by", 1..)?;
+            let comparator = args.next().unwrap();
+            sorted_map(Some(comparator), args, &env)
+        }),
+    );
+    env.set(
+        "sorted-set",
+        make_fn_val(|args, env| sorted_set(None, args, &env)),
+    );
+    env.set(
+        "sorted-set-by",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "sorted-set-by", 1..)?;
+            let comparator = args.next().unwrap();
+            sorted_set(Some(comparator), args, &env)
+        }),
+    );
+    env.set(
+        "sorted?",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "sorted?", 1..=1)?;
+            Ok(Value::Bool(matches!(
+                args.next().unwrap(),
+                Value::SortedMap(_, _) | Value::SortedSet(_, _)
+            )))
+        }),
+    );
+    env.set("subseq", make_fn_val(|args, env| subseq(args, true, &env)));
+    env.set(
+        "rsubseq",
+        make_fn_val(|args, env| subseq(args, false, &env)),
+    );
+}
+
+impl<V: Clone> Sorted<V> {
+    pub(super) fn new(comparator: Option<Value>) -> Self {
+        Self {
+            entries: PersistentSortedMap::new(),
+            comparator: comparator.map(Box::new),
+        }
+    }
+
+    pub(super) fn get(
+        &self,
+        key: &Value,
+        env: &Rc<RefCell<Env>>,
+    ) -> RuntimeResult<Option<(&Value, &V)>> {
+        let comparator = &self.comparator;
+        self.entries
+            .get_key_value(key, &mut |a, b| compare(comparator, a, b, env))
+    }
+
+    pub(super) fn insert(
+        &mut self,
+        key: Value,
+        value: V,
+        env: &Rc<RefCell<Env>>,
+    ) -> RuntimeResult<()> {
+        let comparator = &self.comparator;
+        self.entries
+            .insert(key, value, &mut |a, b| compare(comparator, a, b, env))
+    }
+
+    /// Returns whether the key was present.
+    pub(super) fn remove(&mut self, key: &Value, env: &Rc<RefCell<Env>>) -> RuntimeResult<bool> {
+        let comparator = &self.comparator;
+        self.entries
+            .remove(key, &mut |a, b| compare(comparator, a, b, env))
+    }
+}
+
+/// Compares two keys with the comparator of a sorted collection, or `compare` if it has none.
+fn compare(
+    comparator: &Option<Box<Value>>,
+    a: &Value,
+    b: &Value,
+    env: &Rc<RefCell<Env>>,
+) -> RuntimeResult<Ordering> {
+    match comparator {
+        Some(f) => call_comparator(f, a, b, env),
+        None => Ok(a.cmp(b)),
+    }
+}
+
+fn sorted_map(
+    comparator: Option<Value>,
+    mut args: Args,
+    env: &Rc<RefCell<Env>>,
+) -> RuntimeResult<Value> {
+    ensure_even_args(&args)?;
+    let mut map = Sorted::new(comparator);
+    while let Some(key) = args.next() {
+        map.insert(key, args.next().unwrap(), env)?;
+    }
+    Ok(Value::SortedMap(map, Box::new(Value::Nil)))
+}
+
+fn sorted_set(
+    comparator: Option<Value>,
+    args: Args,
+    env: &Rc<RefCell<Env>>,
+) -> RuntimeResult<Value> {
+    let mut set = Sorted::new(comparator);
+    for v in args {
+        set.insert(v, (), env)?;
+    }
+    Ok(Value::SortedSet(set, Box::new(Value::Nil)))
+}
+
+/// A bound of a range query: the key, and whether it is included.
+struct Bound {
+    key: Value,
+    inclusive: bool,
+}
+
+/// Implements `(subseq sc test key)` and `(subseq sc start-test start-key end-test end-key)`,
+/// and `rsubseq` if not `ascending`.
+///
+/// Like in Clojure, the tests are `<`, `<=`, `>` or `>=`, applied to the result of comparing
+/// an entry to the key and 0. So a test is classified by calling it on `(1 0)` and `(0 0)`:
+/// it's a lower bound if `1` passes, and inclusive if `0` passes.
+fn subseq(mut args: Args, ascending: bool, env: &Rc<RefCell<Env>>) -> RuntimeResult<Value> {
+    let name = if ascending { "subseq" } else { "rsubseq" };
+    if args.len() != 3 && args.len() != 5 {
+        return Err(runtime_errors::wrong_arity(name, args.len()));
+    }
+    let coll = args.next().unwrap();
+    let (mut lower, mut upper) = (None, None);
+    while let Some(test) = args.next() {
+        let key = args.next().unwrap();
+        let passes = |n: i64| -> RuntimeResult<bool> {
+            let args = vec![Value::Number(Int::Small(n)), Value::Number(Int::Small(0))];
+            Ok(!matches!(
+                eval_fn_no_tco(test.clone(), args, env.clone())?,
+                Value::Bool(false) | Value::Nil
+            ))
+        };
+        let bound = Some(Bound {
+            key,
+            inclusive: passes(0)?,
+        });
+        if passes(1)? {
+            lower = bound;
+        } else {
+            upper = bound;
+        }
+    }
+    let (start, end) = if ascending {
+        (lower, upper)
+    } else {
+        (upper, lower)
+    };
+    let entries = match &coll {
+        Value::SortedMap(map, _) => range(map, start, end, ascending, env)?
+            .into_iter()
+            .map(|(k, v)| Value::map_entry(k, v))
+            .collect::<Vec<_>>(),
+        Value::SortedSet(set, _) => range(set, start, end, ascending, env)?
+            .into_iter()
+            .map(|(k, _)| k.clone())
+            .collect(),
+        v => return Err(runtime_errors::not_a("sorted map or sorted set", v)),
+    };
+    if entries.is_empty() {
+        Ok(Value::Nil)
+    } else {
+        Ok(Value::List(entries, Box::new(Value::Nil)))
+    }
+}
+
+/// Collects the entries from `start` to `end` in iteration order.
+fn range<'a, V: Clone>(
+    sorted: &'a Sorted<V>,
+    start: Option<Bound>,
+    end: Option<Bound>,
+    ascending: bool,
+    env: &Rc<RefCell<Env>>,
+) -> RuntimeResult<Vec<(&'a Value, &'a V)>> {
+    let mut cmp = |a: &Value, b: &Value| compare(&sorted.comparator, a, b, env);
+    let iter: sorted_map::Iter<'_, Value, V> = match &start {
+        Some(start) => {
+            sorted
+                .entries
+                .range_from(&start.key, start.inclusive, ascending, &mut cmp)?
+        }
+        None if ascending => sorted.entries.iter(),
+        None => sorted.entries.iter_rev(),
+    };
+    let before_end = if ascending {
+        Ordering::Less
+    } else {
+        Ordering::Greater
+    };
+    let mut entries = Vec::new();
+    for (k, v) in iter {
+        if let Some(end) = &end {
+            let ordering = cmp(k, &end.key)?;
+            if ordering != before_end && !(end.inclusive && ordering == Ordering::Equal) {
+                break;
+            }
+        }
+        entries.push((k, v));
+    }
+    Ok(entries)
+}
@@ -0,0 +1,389 @@
+use super::{ensure_arity, make_fn_val, pattern::match_value};
+use crate::{
+    env::Env,
+    eval_fn_no_tco,
+    number::Int,
+    printer::pr_str,
+    runtime_errors::{self, RuntimeResult},
+    value::Value,
+};
+
+type Args = std::vec::IntoIter<Value>;
+
+// All indices are counted in characters, not bytes. `reverse` is in `seq`, and reverses a
+// string into a string.
+pub fn init_env(env: &mut Env) {
+    env.set(
+        "subs",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "subs", 2..=3)?;
+            let s = next_string(&mut args)?;
+            let len = s.chars().count();
+            let start = next_index(&mut args, len)?;
+            let end = match args.next() {
+                Some(end) => index(end, len)?,
+                None => len,
+            };
+            if start > end {
+                return Err(runtime_errors::out_of_bounds(end, start));
+            }
+            Ok(Value::String(
+                s.chars().skip(start).take(end - start).collect(),
+            ))
+        }),
+    );
+    env.set(
+        "index-of",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "index-of", 2..=3)?;
+            let s = next_string(&mut args)?;
+            let needle = next_needle(&mut args)?;
+            let from = match args.next() {
+                Some(from) => index(from, s.chars().count())?,
+                None => 0,
+            };
+            let from = byte_offset(&s, from);
+            Ok(char_index_value(
+                &s,
+                s[from..].find(&needle).map(|i| i + from),
+            ))
+        }),
+    );
+    env.set(
+        "last-index-of",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "last-index-of", 2..=3)?;
+            let s = next_string(&mut args)?;
+            let needle = next_needle(&mut args)?;
+            // a match may start at `from` at the latest
+            let end = match args.next() {
+                Some(from) => {
+                    (byte_offset(&s, index(from, s.chars().count())?) + needle.len()).min(s.len())
+                }
+                None => s.len(),
+            };
+            let end = floor_char_boundary(&s, end);
+            Ok(char_index_value(&s, s[..end].rfind(&needle)))
+        }),
+    );
+    env.set(
+        "split",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "split", 2..=3)?;
+            let s = next_string(&mut args)?;
+            let separator = args.next().unwrap();
+            let limit = args.next().map(|l| l.try_into_int()).transpose()?;
+            Ok(string_vec(split(&s, separator, limit)?))
+        }),
+    );
+    env.set(
+        "split-lines",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "split-lines", 1..=1)?;
+            Ok(string_vec(
+                next_string(&mut args)?.lines().map(str::to_owned).collect(),
+            ))
+        }),
+    );
+    env.set(
+        "join",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "join", 1..=2)?;
+            let separator = if args.len() == 2 {
+                next_string(&mut args)?
+            } else {
+                String::new()
+            };
+            let coll = args.next().unwrap().seq_elements()?;
+            let mut joined = String::new();
+            for (i, item) in coll.iter().enumerate() {
+                if i != 0 {
+                    joined.push_str(&separator);
+                }
+                item.realize()?;
+                pr_str(item, &mut joined, false).unwrap();
+            }
+            Ok(Value::String(joined))
+        }),
+    );
+    env.set(
+        "trim",
+        make_fn_val(|args, _| map_string(args, "trim", |s| s.trim().to_owned())),
+    );
+    env.set(
+        "triml",
+        make_fn_val(|args, _| map_string(args, "triml", |s| s.trim_start().to_owned())),
+    );
+    env.set(
+        "trimr",
+        make_fn_val(|args, _| map_string(args, "trimr", |s| s.trim_end().to_owned())),
+    );
+    env.set(
+        "upper-case",
+        make_fn_val(|args, _| map_string(args, "upper-case", str::to_uppercase)),
+    );
+    env.set(
+        "lower-case",
+        make_fn_val(|args, _| map_string(args, "lower-case", str::to_lowercase)),
+    );
+    env.set(
+        "capitalize",
+        make_fn_val(|args, _| {
+            map_string(args, "capitalize", |s| {
+                let mut chars = s.chars();
+                match chars.next() {
+                    Some(first) => first
+                        .to_uppercase()
+                        .chain(chars.as_str().to_lowercase().chars())
+                        .collect(),
+                    None => String::new(),
+                }
+            })
+        }),
+    );
+    env.set(
+        "replace",
+        make_fn_val(|mut args, env| {
+            ensure_arity(&args, "replace", 3..=3)?;
+            let s = next_string(&mut args)?;
+            match (args.next().unwrap(), args.next().unwrap()) {
+                (Value::String(pattern), _) if pattern.is_empty() => Err(
+                    runtime_errors::error_to_string("replace: pattern must not be empty"),
+                ),
+                (Value::String(pattern), replacement) => Ok(Value::String(
+                    s.replace(&pattern, replacement.try_as_str()?),
+                )),
+                (Value::Char(pattern), Value::Char(replacement)) => Ok(Value::String(
+                    s.replace(pattern, replacement.encode_utf8(&mut [0; 4])),
+                )),
+                // `$1` or `${name}` in the replacement refer to groups of the match
+                (Value::Regex(pattern), Value::String(replacement)) => Ok(Value::String(
+                    pattern
+                        .regex()
+                        .replace_all(&s, replacement.as_str())
+                        .into_owned(),
+                )),
+                (Value::Regex(pattern), f) => {
+                    let mut replaced = String::new();
+                    let mut last = 0;
+                    for caps in pattern.regex().captures_iter(&s) {
+                        let whole = caps.get(0).unwrap();
+                        replaced.push_str(&s[last..whole.start()]);
+                        last = whole.end();
+                        let replacement =
+                            eval_fn_no_tco(f.clone(), vec![match_value(caps)], env.clone())?;
+                        replaced.push_str(replacement.try_as_str()?);
+                    }
+                    replaced.push_str(&s[last..]);
+                    Ok(Value::String(replaced))
+                }
+                (v, _) => Err(runtime_errors::not_a("string, character or regex", &v)),
+            }
+        }),
+    );
+    env.set(
+        "starts-with?",
+        make_fn_val(|args, _| test_strings(args, "starts-with?", |s, p| s.starts_with(p))),
+    );
+    env.set(
+        "ends-with?",
+        make_fn_val(|args, _| test_strings(args, "ends-with?", |s, p| s.ends_with(p))),
+    );
+    env.set(
+        "includes?",
+        make_fn_val(|args, _| test_strings(args, "includes?", |s, p| s.contains(p))),
+    );
+    env.set(
+        "char",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "char", 1..=1)?;
+            match args.next().unwrap() {
+                c @ Value::Char(_) => Ok(c),
+                Value::Number(n) => n
+                    .to_u32()
+                    .and_then(char::from_u32)
+                    .map(Value::Char)
+                    .ok_or_else(|| runtime_errors::not_a("character code", &Value::Number(n))),
+                v => Err(runtime_errors::not_a("integer or character", &v)),
+            }
+        }),
+    );
+    env.set(
+        "char?",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "char?", 1..=1)?;
+            Ok(Value::Bool(matches!(args.next().unwrap(), Value::Char(_))))
+        }),
+    );
+    env.set(
+        "blank?",
+        make_fn_val(|mut args, _| {
+            ensure_arity(&args, "blank?", 1..=1)?;
+            match args.next().unwrap() {
+                Value::Nil => Ok(Value::Bool(true)),
+                v => Ok(Value::Bool(v.try_as_str()?.trim().is_empty())),
+            }
+        }),
+    );
+}
+
+fn next_string(args: &mut Args) -> RuntimeResult<String> {
+    args.next().unwrap().try_into_string()
+}
+
+/// A string to search for, which may also be given as a single character.
+fn next_needle(args: &mut Args) -> RuntimeResult<String> {
+    match args.next().unwrap() {
+        Value::String(s) => Ok(s),
+        Value::Char(c) => Ok(c.into()),
+        v => Err(runtime_errors::not_a("string or character", &v)),
+    }
+}
+
+fn index(v: Value, len: usize) -> RuntimeResult<usize> {
+    let n = v.try_into_int()?;
+    match n.to_usize() {
+        Some(i) if i <= len => Ok(i),
+        _ => Err(runtime_errors::out_of_bounds(len, n)),
+    }
+}
+
+fn next_index(args: &mut Args, len: usize) -> RuntimeResult<usize> {
+    index(args.next().unwrap(), len)
+}
+
+fn byte_offset(s: &str, char_index: usize) -> usize {
+    s.char_indices()
+        .nth(char_index)
+        .map_or(s.len(), |(offset, _)| offset)
+}
+
+fn floor_char_boundary(s: &str, mut offset: usize) -> usize {
+    while !s.is_char_boundary(offset) {
+        offset -= 1;
+    }
+    offset
+}
+
+/// Converts a byte offset found by searching `s` into a character index, or nil.
+fn char_index_value(s: &str, byte_offset: Option<usize>) -> Value {
+    match byte_offset {
+        Some(offset) => Value::Number(Int::from(s[..offset].chars().count())),
+        None => Value::Nil,
+    }
+}
+
+fn string_vec(strings: Vec<String>) -> Value {
+    Value::Vec(
+        strings.into_iter().map(Value::String).collect(),
+        Box::new(Value::Nil),
+    )
+}
+
+/// Splits like Clojure's `split`: without a limit, trailing empty strings are removed,
+/// and an empty separator splits into single characters.
+/// The separator may be a string or a regex.
+fn split(s: &str, separator: Value, limit: Option<Int>) -> RuntimeResult<Vec<String>> {
+    let max_parts = limit.as_ref().and_then(Int::to_usize).filter(|l| *l > 0);
+    let mut parts: Vec<String> = match separator {
+        Value::String(separator) if separator.is_empty() => s.chars().map(String::from).collect(),
+        Value::String(separator) => match max_parts {
+            Some(limit) => s.splitn(limit, &separator).map(str::to_owned).collect(),
+            None => s.split(&separator).map(str::to_owned).collect(),
+        },
+        Value::Regex(separator) => match max_parts {
+            Some(limit) => separator
+                .regex()
+                .splitn(s, limit)
+                .map(str::to_owned)
+                .collect(),
+            None => separator.regex().split(s).map(str::to_owned).collect(),
+        },
+        v => return Err(runtime_errors::not_a("string or regex", &v)),
+    };
+    if limit.is_none() {
+        while parts.len() > 1 && parts.last().is_some_and(String::is_empty) {
+            parts.pop();
+        }
+    }
+    Ok(parts)
+}
+
+fn map_string(mut args: Args, name: &str, f: fn(&str) -> String) -> RuntimeResult<Value> {
+    ensure_arity(&args, name, 1..=1)?;
+    Ok(Value::String(f(&next_string(&mut args)?)))
+}
+
+fn test_strings(mut args: Args, name: &str, f: fn(&str, &str) -> bool) -> RuntimeResult<Value> {
+    ensure_arity(&args, name, 2..=2)?;
+    let s = next_string(&mut args)?;
+    Ok(Value::Bool(f(&s, &next_needle(&mut args)?)))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::mal_core::tests::{assert_evals_to, assert_fails};
+
+    #[test]
+    fn indices_count_characters() {
+        assert_evals_to(r#"(subs "héllo" 1 2)"#, r#""é""#);
+        assert_evals_to(r#"(subs "héllo" 2)"#, r#""llo""#);
+        assert_evals_to(r#"(subs "héllo" 5)"#, r#""""#);
+        assert_fails(r#"(subs "héllo" 6)"#);
+        assert_fails(r#"(subs "héllo" 3 2)"#);
+        assert_evals_to(r#"(index-of "héllo" "l")"#, "2");
+        assert_evals_to(r#"(index-of "héllo" \l 3)"#, "3");
+        assert_evals_to(r#"(index-of "héllo" "x")"#, "nil");
+        assert_evals_to(r#"(last-index-of "héllo" "l")"#, "3");
+        assert_evals_to(r#"(last-index-of "héllo" "l" 2)"#, "2");
+        assert_evals_to(r#"(last-index-of "héllo" "é" 0)"#, "nil");
+    }
+
+    #[test]
+    fn split_and_join() {
+        assert_evals_to(r#"(split "a,b,,c,," ",")"#, r#"["a" "b" "" "c"]"#);
+        assert_evals_to(r#"(split "a,b,c" "," 2)"#, r#"["a" "b,c"]"#);
+        assert_evals_to(r#"(split "héj" "")"#, r#"["h" "é" "j"]"#);
+        assert_evals_to(r#"(split "a1b22c" #"\d+")"#, r#"["a" "b" "c"]"#);
+        assert_evals_to(r#"(split-lines "a\nb\nc")"#, r#"["a" "b" "c"]"#);
+        assert_evals_to(r#"(join ", " [1 "a" :b])"#, r#""1, a, :b""#);
+        assert_evals_to(r#"(join (list 1 2))"#, r#""12""#);
+    }
+
+    #[test]
+    fn transformations() {
+        assert_evals_to(r#"(trim "  a b \n")"#, r#""a b""#);
+        assert_evals_to(r#"(triml "  a ")"#, r#""a ""#);
+        assert_evals_to(r#"(trimr "  a ")"#, r#""  a""#);
+        assert_evals_to(r#"(upper-case "straße")"#, r#""STRASSE""#);
+        assert_evals_to(r#"(lower-case "ÉA")"#, r#""éa""#);
+        assert_evals_to(r#"(capitalize "éCOLE")"#, r#""École""#);
+        assert_evals_to(r#"(capitalize "")"#, r#""""#);
+        assert_evals_to(r#"(reverse "héllo")"#, r#""olléh""#);
+    }
+
+    #[test]
+    fn replace() {
+        assert_evals_to(r#"(replace "a.b.c" "." "-")"#, r#""a-b-c""#);
+        assert_evals_to(r#"(replace "aba" \a \é)"#, r#""ébé""#);
+        assert_evals_to(r#"(replace "a1b22" #"(\d+)" "<$1>")"#, r#""a<1>b<22>""#);
+        assert_evals_to(
+            r#"(replace "a1b22" #"\d+" (fn* [m] (str (count m))))"#,
+            r#""a1b2""#,
+        );
+        assert_fails(r#"(replace "abc" "" "x")"#);
+    }
+
+    #[test]
+    fn predicates() {
+        assert_evals_to(r#"(starts-with? "héllo" "hé")"#, "true");
+        assert_evals_to(r#"(ends-with? "héllo" \o)"#, "true");
+        assert_evals_to(r#"(includes? "héllo" "x")"#, "false");
+        assert_evals_to(r#"(blank? "  ")"#, "true");
+        assert_evals_to("(blank? nil)", "true");
+        assert_evals_to(r#"(blank? "a")"#, "false");
+        assert_evals_to("(char 233)", r"\é");
+        assert_evals_to(r"(char? \a)", "true");
+        assert_fails("(char -1)");
+    }
+}
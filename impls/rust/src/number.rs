@@ -0,0 +1,279 @@
+use std::{
+    cmp::Ordering,
+    convert::TryFrom,
+    fmt,
+    ops::{Add, BitAnd, BitOr, BitXor, Mul, Neg, Sub},
+};
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+use num_traits::{Signed, ToPrimitive, Zero};
+
+/// An integer that is stored inline as long as it fits into an i64 and
+/// is promoted to a heap-allocated bignum otherwise.
+///
+/// `Big` is only ever used for values that don't fit into an i64, so two
+/// equal integers always have the same representation.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Int {
+    Small(i64),
+    Big(BigInt),
+}
+
+impl Int {
+    pub fn to_big(&self) -> BigInt {
+        match self {
+            Int::Small(n) => BigInt::from(*n),
+            Int::Big(n) => n.clone(),
+        }
+    }
+
+    pub fn to_ratio(&self) -> BigRational {
+        BigRational::from_integer(self.to_big())
+    }
+
+    pub fn to_f64(&self) -> f64 {
+        match self {
+            Int::Small(n) => *n as f64,
+            Int::Big(n) => n.to_f64().unwrap_or(f64::NAN),
+        }
+    }
+
+    pub fn to_usize(&self) -> Option<usize> {
+        match self {
+            Int::Small(n) => n.to_usize(),
+            Int::Big(n) => n.to_usize(),
+        }
+    }
+
+    pub fn to_u32(&self) -> Option<u32> {
+        match self {
+            Int::Small(n) => n.to_u32(),
+            Int::Big(n) => n.to_u32(),
+        }
+    }
+
+    pub fn is_zero(&self) -> bool {
+        matches!(self, Int::Small(0))
+    }
+
+    pub fn is_negative(&self) -> bool {
+        match self {
+            Int::Small(n) => *n < 0,
+            Int::Big(n) => n.is_negative(),
+        }
+    }
+
+    pub fn is_even(&self) -> bool {
+        match self {
+            Int::Small(n) => n % 2 == 0,
+            Int::Big(n) => (n % 2u8).is_zero(),
+        }
+    }
+
+    pub fn abs(self) -> Int {
+        if self.is_negative() {
+            -self
+        } else {
+            self
+        }
+    }
+
+    /// Applies `small` if both operands are inline and it doesn't overflow,
+    /// and `big` on the promoted operands otherwise.
+    fn binary_op(
+        self,
+        rhs: Self,
+        small: impl FnOnce(i64, i64) -> Option<i64>,
+        big: impl FnOnce(BigInt, BigInt) -> BigInt,
+    ) -> Int {
+        if let (Int::Small(a), Int::Small(b)) = (&self, &rhs) {
+            if let Some(n) = small(*a, *b) {
+                return Int::Small(n);
+            }
+        }
+        big(self.to_big(), rhs.to_big()).into()
+    }
+
+    /// Division truncating towards zero. Returns `None` when dividing by zero.
+    pub fn quot(self, rhs: Self) -> Option<Int> {
+        if rhs.is_zero() {
+            return None;
+        }
+        Some(self.binary_op(rhs, i64::checked_div, |a, b| a / b))
+    }
+
+    /// Remainder of `quot`, which has the sign of the dividend.
+    pub fn rem(self, rhs: Self) -> Option<Int> {
+        if rhs.is_zero() {
+            return None;
+        }
+        Some(self.binary_op(rhs, i64::checked_rem, |a, b| a % b))
+    }
+
+    /// Remainder of flooring division, which has the sign of the divisor.
+    pub fn modulo(self, rhs: Self) -> Option<Int> {
+        if rhs.is_zero() {
+            return None;
+        }
+        Some(self.binary_op(
+            rhs,
+            |a, b| {
+                let r = a.checked_rem(b)?;
+                Some(if r != 0 && (r < 0) != (b < 0) {
+                    r + b
+                } else {
+                    r
+                })
+            },
+            |a, b| {
+                let r = &a % &b;
+                if !r.is_zero() && r.is_negative() != b.is_negative() {
+                    r + b
+                } else {
+                    r
+                }
+            },
+        ))
+    }
+
+    pub fn pow(self, exp: u32) -> Int {
+        match self {
+            Int::Small(n) => match n.checked_pow(exp) {
+                Some(n) => Int::Small(n),
+                None => BigInt::from(n).pow(exp).into(),
+            },
+            Int::Big(n) => n.pow(exp).into(),
+        }
+    }
+
+    pub fn shl(self, bits: usize) -> Int {
+        if let Int::Small(n) = self {
+            if bits < 64 && (n << bits) >> bits == n {
+                return Int::Small(n << bits);
+            }
+        }
+        (self.to_big() << bits).into()
+    }
+
+    /// Arithmetic shift, rounding towards negative infinity.
+    pub fn shr(self, bits: usize) -> Int {
+        match self {
+            Int::Small(n) => Int::Small(n >> bits.min(63)),
+            Int::Big(n) => (n >> bits).into(),
+        }
+    }
+}
+
+impl From<i64> for Int {
+    fn from(n: i64) -> Self {
+        Int::Small(n)
+    }
+}
+
+impl From<usize> for Int {
+    fn from(n: usize) -> Self {
+        match i64::try_from(n) {
+            Ok(n) => Int::Small(n),
+            Err(_) => Int::Big(BigInt::from(n)),
+        }
+    }
+}
+
+impl From<u128> for Int {
+    fn from(n: u128) -> Self {
+        BigInt::from(n).into()
+    }
+}
+
+impl From<BigInt> for Int {
+    fn from(n: BigInt) -> Self {
+        match n.to_i64() {
+            Some(n) => Int::Small(n),
+            None => Int::Big(n),
+        }
+    }
+}
+
+impl fmt::Display for Int {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Int::Small(n) => write!(f, "{}", n),
+            Int::Big(n) => write!(f, "{}", n),
+        }
+    }
+}
+
+impl PartialOrd for Int {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Int {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (Int::Small(a), Int::Small(b)) => a.cmp(b),
+            (a, b) => a.to_big().cmp(&b.to_big()),
+        }
+    }
+}
+
+impl Neg for Int {
+    type Output = Int;
+
+    fn neg(self) -> Self::Output {
+        match self {
+            Int::Small(n) => match n.checked_neg() {
+                Some(n) => Int::Small(n),
+                None => (-BigInt::from(n)).into(),
+            },
+            Int::Big(n) => (-n).into(),
+        }
+    }
+}
+
+macro_rules! impl_binary_op {
+    ($trait:ident, $method:ident, $small:expr) => {
+        impl $trait for Int {
+            type Output = Int;
+
+            fn $method(self, rhs: Self) -> Self::Output {
+                self.binary_op(rhs, $small, |a, b| a.$method(b))
+            }
+        }
+    };
+}
+
+impl_binary_op!(Add, add, i64::checked_add);
+impl_binary_op!(Sub, sub, i64::checked_sub);
+impl_binary_op!(Mul, mul, i64::checked_mul);
+impl_binary_op!(BitAnd, bitand, |a, b| Some(a & b));
+impl_binary_op!(BitOr, bitor, |a, b| Some(a | b));
+impl_binary_op!(BitXor, bitxor, |a, b| Some(a ^ b));
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overflow_promotes() {
+        let max = Int::Small(i64::MAX);
+        assert!(matches!(max.clone() + Int::Small(1), Int::Big(_)));
+        assert_eq!((max.clone() + Int::Small(1)) - Int::Small(1), max);
+        assert!(matches!(-Int::Small(i64::MIN), Int::Big(_)));
+        assert!(matches!(Int::Small(1).shl(63), Int::Big(_)));
+    }
+
+    #[test]
+    fn division() {
+        assert_eq!(Int::Small(-7).quot(Int::Small(2)), Some(Int::Small(-3)));
+        assert_eq!(Int::Small(-7).rem(Int::Small(2)), Some(Int::Small(-1)));
+        assert_eq!(Int::Small(-7).modulo(Int::Small(2)), Some(Int::Small(1)));
+        assert_eq!(
+            Int::Small(i64::MIN).modulo(Int::Small(-1)),
+            Some(Int::Small(0))
+        );
+        assert_eq!(Int::Small(1).quot(Int::Small(0)), None);
+    }
+}
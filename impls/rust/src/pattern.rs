@@ -0,0 +1,36 @@
+use std::cell::OnceCell;
+
+use regex::Regex;
+
+/// A compiled regular expression, as created by a `#"..."` literal or `re-pattern`.
+#[derive(Debug)]
+pub struct Pattern {
+    regex: Regex,
+    // only compiled when the pattern is first used with `re-matches`
+    anchored: OnceCell<Regex>,
+}
+
+impl Pattern {
+    pub fn new(source: &str) -> Result<Self, regex::Error> {
+        Ok(Self {
+            regex: Regex::new(source)?,
+            anchored: OnceCell::new(),
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        self.regex.as_str()
+    }
+
+    pub fn regex(&self) -> &Regex {
+        &self.regex
+    }
+
+    /// The same pattern, but only matching the whole input.
+    pub fn anchored(&self) -> &Regex {
+        self.anchored.get_or_init(|| {
+            Regex::new(&format!("^(?:{})$", self.as_str()))
+                .expect("anchoring a valid regex keeps it valid")
+        })
+    }
+}
@@ -3,9 +3,7 @@ use std::{
     fmt::{Display, Write},
 };
 
-use rustc_hash::FxHashMap;
-
-use crate::value::Value;
+use crate::{lazy_seq, tokenize::CHAR_NAMES, value::Value};
 
 impl Display for Value {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -14,61 +12,157 @@ impl Display for Value {
 }
 
 pub fn pr_str(value: &Value, f: &mut impl Write, readably: bool) -> std::fmt::Result {
+    write_value(value, f, readably, true)
+}
+
+/// Prints a value readably for an error message. Unlike `pr_str`, it doesn't realize lazy
+/// sequences, which may be infinite: it prints the elements realized so far, up to a limit,
+/// followed by `...` if there may be more.
+pub fn describe(value: &Value) -> String {
+    let mut buf = String::new();
+    write_value(value, &mut buf, true, false).unwrap();
+    buf
+}
+
+/// The number of elements of a lazy sequence that `describe` prints.
+const DESCRIBED_ELEMENTS: usize = 10;
+
+fn write_value(
+    value: &Value,
+    f: &mut impl Write,
+    readably: bool,
+    realize: bool,
+) -> std::fmt::Result {
     match value {
         Value::Number(n) => write!(f, "{}", n),
-        Value::Symbol(name) => write!(f, "{}", name),
+        Value::Ratio(r) => write!(f, "{}", r),
+        Value::Float(n) => write_float(f, *n),
         Value::List(list, _) => {
             write!(f, "(")?;
-            write_list(f, list, readably)?;
+            write_list(f, list.iter(), readably, realize)?;
+            write!(f, ")")
+        }
+        Value::LazySeq(seq, _) => {
+            // lazy sequences are realized by the callers of the printer, which can report
+            // errors. One that fails anyway is printed as far as it got, like in `describe`.
+            let (elements, more) = match realize.then(|| lazy_seq::realized_elements(seq)) {
+                Some(Ok(elements)) => (elements, false),
+                _ => lazy_seq::realized_prefix(seq, DESCRIBED_ELEMENTS),
+            };
+            write!(f, "(")?;
+            write_list(f, elements.iter(), readably, realize)?;
+            if more {
+                f.write_str(if elements.is_empty() { "..." } else { " ..." })?;
+            }
             write!(f, ")")
         }
         Value::Vec(list, _) => {
             write!(f, "[")?;
-            write_list(f, list, readably)?;
+            write_list(f, list.iter(), readably, realize)?;
             write!(f, "]")
         }
-        Value::Keyword(name) => write!(
-            f,
-            "{}",
-            name.strip_prefix(char::from_u32(0x29E).unwrap()).unwrap()
-        ),
+        Value::Symbol(name) | Value::Keyword(name) => write!(f, "{}", name),
         Value::String(value) => {
             if readably {
-                write!(f, "{:?}", value)
+                write_escaped(f, value)
             } else {
                 write!(f, "{}", value)
             }
         }
-        Value::Map(map, _) => write_map(f, map, readably),
+        Value::Char(c) => {
+            if !readably {
+                write!(f, "{}", c)
+            } else if let Some((_, name)) = CHAR_NAMES.iter().find(|(named, _)| named == c) {
+                write!(f, "\\{}", name)
+            } else if c.is_control() {
+                write!(f, "\\u{:04X}", *c as u32)
+            } else {
+                write!(f, "\\{}", c)
+            }
+        }
+        Value::Regex(pattern) => {
+            if readably {
+                write!(f, "#\"{}\"", pattern.as_str())
+            } else {
+                write!(f, "{}", pattern.as_str())
+            }
+        }
+        Value::Map(map, _) => write_map(f, map.iter(), readably, realize),
+        Value::SortedMap(map, _) => write_map(f, map.entries.iter(), readably, realize),
+        Value::Set(_, _) | Value::SortedSet(_, _) => {
+            write!(f, "#{{")?;
+            write_list(f, value.try_iter_set().unwrap(), readably, realize)?;
+            write!(f, "}}")
+        }
         Value::HostFn(_, _) | Value::Closure(_, _) => write!(f, "#<function>"),
         Value::Nil => write!(f, "nil"),
         Value::Bool(b) => write!(f, "{}", b),
-        Value::Atom(atom) => write!(f, "(atom {})", RefCell::borrow(atom)),
+        Value::Atom(atom) => {
+            write!(f, "(atom ")?;
+            write_value(&RefCell::borrow(atom), f, readably, realize)?;
+            write!(f, ")")
+        }
+    }
+}
+
+/// Writes a string literal that the reader reads back as the same string.
+fn write_escaped(f: &mut impl Write, s: &str) -> std::fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\t' => f.write_str("\\t")?,
+            '\r' => f.write_str("\\r")?,
+            '\0' => f.write_str("\\0")?,
+            c if c.is_control() => write!(f, "\\u{:04X}", c as u32)?,
+            c => f.write_char(c)?,
+        }
     }
+    f.write_char('"')
 }
 
-fn write_list(f: &mut impl Write, list: &[Value], readably: bool) -> std::fmt::Result {
-    for (i, elem) in list.iter().enumerate() {
+fn write_float(f: &mut impl Write, n: f64) -> std::fmt::Result {
+    if n.is_nan() {
+        write!(f, "##NaN")
+    } else if n.is_infinite() {
+        write!(f, "##{}Inf", if n < 0.0 { "-" } else { "" })
+    } else {
+        // Debug always includes a decimal point or an exponent, so the output reads back as a float.
+        write!(f, "{:?}", n)
+    }
+}
+
+fn write_list<'a>(
+    f: &mut impl Write,
+    list: impl Iterator<Item = &'a Value>,
+    readably: bool,
+    realize: bool,
+) -> std::fmt::Result {
+    for (i, elem) in list.enumerate() {
         if i != 0 {
             f.write_char(' ')?;
         }
-        pr_str(elem, f, readably)?;
+        write_value(elem, f, readably, realize)?;
     }
     Ok(())
 }
 
-fn write_map(f: &mut impl Write, map: &FxHashMap<String, Value>, readably: bool) -> std::fmt::Result {
+fn write_map<'a>(
+    f: &mut impl Write,
+    entries: impl Iterator<Item = (&'a Value, &'a Value)>,
+    readably: bool,
+    realize: bool,
+) -> std::fmt::Result {
     write!(f, "{{")?;
-    for (i, (key, value)) in map.iter().enumerate() {
+    for (i, (key, value)) in entries.enumerate() {
         if i != 0 {
             write!(f, " ")?;
         }
-        if let Some(keyword) = key.strip_prefix(char::from_u32(0x29E).unwrap()) {
-            write!(f, "{} ", keyword)?;
-        } else {
-            write!(f, "{:?} ", key)?;
-        }
-        pr_str(value, f, readably)?;
+        write_value(key, f, readably, realize)?;
+        f.write_char(' ')?;
+        write_value(value, f, readably, realize)?;
     }
     write!(f, "}}")
 }
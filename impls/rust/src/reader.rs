@@ -1,8 +1,9 @@
-use std::fmt::Display;
-
-use rustc_hash::FxHashMap;
+use std::{fmt::Display, rc::Rc};
 
 use crate::{
+    hash_map::PersistentHashMap,
+    hash_set::PersistentHashSet,
+    pattern::Pattern,
     tokenize::{Token, Tokenizer},
     value::Value,
 };
@@ -13,10 +14,14 @@ pub enum ParseError {
     UnexpectedEof,
     UnterminatedString,
     EmptyKeyword,
-    InvalidNumber,
+    InvalidNumber(String),
     UnexpectedToken,
     InvalidStringEscape(char),
-    InvalidMapKey(String),
+    InvalidUnicodeEscape(String),
+    InvalidSymbolicValue(String),
+    InvalidRegex(String),
+    InvalidCharacter(String),
+    DuplicateSetElement(String),
 }
 
 impl Display for ParseError {
@@ -26,11 +31,15 @@ impl Display for ParseError {
             ParseError::UnexpectedEof | ParseError::UnterminatedString => {
                 write!(f, "unexpected end of input")
             }
-            ParseError::InvalidNumber => write!(f, "invalid number"),
+            ParseError::InvalidNumber(n) => write!(f, "invalid number '{}'", n),
             ParseError::EmptyKeyword => write!(f, "empty keyword"),
             ParseError::UnexpectedToken => write!(f, "unexpected token"),
             ParseError::InvalidStringEscape(c) => write!(f, "invalid string escape: \\{}", c),
-            ParseError::InvalidMapKey(k) => write!(f, "invalid map key '{}'", k),
+            ParseError::InvalidUnicodeEscape(e) => write!(f, "invalid unicode escape: \\u{}", e),
+            ParseError::InvalidSymbolicValue(v) => write!(f, "invalid symbolic value '##{}'", v),
+            ParseError::InvalidRegex(e) => write!(f, "invalid regex: {}", e),
+            ParseError::DuplicateSetElement(e) => write!(f, "duplicate set element: {}", e),
+            ParseError::InvalidCharacter(c) => write!(f, "invalid character '\\{}'", c),
             ParseError::EmptyInput => unreachable!(),
         }
     }
@@ -77,10 +86,11 @@ impl<'a> Reader<'a> {
                 Box::new(Value::Nil),
             )),
             Token::LeftBracket => Ok(Value::Vec(
-                self.read_list(Token::RightBracket)?,
+                self.read_list(Token::RightBracket)?.into(),
                 Box::new(Value::Nil),
             )),
             Token::LeftBrace => Ok(Value::Map(self.read_map()?, Box::new(Value::Nil))),
+            Token::HashLeftBrace => Ok(Value::Set(self.read_set()?, Box::new(Value::Nil))),
             Token::SingleQuote => {
                 self.next().unwrap();
                 self.read_reader_macro("quote")
@@ -115,7 +125,7 @@ impl<'a> Reader<'a> {
         }
     }
 
-    fn read_reader_macro(&mut self, name: impl Into<String>) -> ParseResult<Value> {
+    fn read_reader_macro(&mut self, name: &str) -> ParseResult<Value> {
         let content = self.read_form()?;
         Ok(Value::List(
             vec![Value::Symbol(name.into()), content],
@@ -133,16 +143,22 @@ impl<'a> Reader<'a> {
         Ok(values)
     }
 
-    fn read_map(&mut self) -> ParseResult<FxHashMap<String, Value>> {
+    fn read_set(&mut self) -> ParseResult<PersistentHashSet<Value>> {
+        let mut set = PersistentHashSet::new();
+        for value in self.read_list(Token::RightBrace)? {
+            let printed = value.to_string();
+            if !set.insert(value) {
+                return Err(ParseError::DuplicateSetElement(printed));
+            }
+        }
+        Ok(set)
+    }
+
+    fn read_map(&mut self) -> ParseResult<PersistentHashMap<Value, Value>> {
         self.next()?;
-        let mut map = FxHashMap::default();
+        let mut map = PersistentHashMap::new();
         while self.peek()? != &Token::RightBrace {
-            map.insert(
-                self.read_form()?
-                    .into_hash_map_key()
-                    .map_err(|e| ParseError::InvalidMapKey(e.to_string()))?,
-                self.read_form()?,
-            );
+            map.insert(self.read_form()?, self.read_form()?);
         }
         self.next()?;
         Ok(map)
@@ -151,13 +167,23 @@ impl<'a> Reader<'a> {
     fn read_atom(&mut self) -> ParseResult<Value> {
         let value = self.next()?;
         Ok(match value {
-            Token::Number(n) => Value::Number(n.parse().map_err(|_| ParseError::InvalidNumber)?),
+            Token::Number(n) => n,
+            Token::SymbolicValue(v) => Value::Float(match v.as_str() {
+                "Inf" => f64::INFINITY,
+                "-Inf" => f64::NEG_INFINITY,
+                "NaN" => f64::NAN,
+                _ => return Err(ParseError::InvalidSymbolicValue(v)),
+            }),
             Token::Ident(value) if value == "true" => Value::Bool(true),
             Token::Ident(value) if value == "false" => Value::Bool(false),
             Token::Ident(value) if value == "nil" => Value::Nil,
-            Token::Ident(value) => Value::Symbol(value),
-            Token::Keyword(value) => Value::Keyword(value),
+            Token::Ident(value) => Value::Symbol(value.into()),
+            Token::Keyword(value) => Value::Keyword(value.into()),
             Token::String(value) => Value::String(value),
+            Token::Char(c) => Value::Char(c),
+            Token::Regex(source) => Value::Regex(Rc::new(
+                Pattern::new(&source).map_err(|e| ParseError::InvalidRegex(e.to_string()))?,
+            )),
             _ => return Err(ParseError::UnexpectedToken),
         })
     }
@@ -170,8 +196,159 @@ pub fn read_str(input: &str) -> ParseResult<Value> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::number::Int;
     #[test]
     fn wat() {
         read_str("~@(1 2 3)").unwrap();
     }
+
+    #[test]
+    fn big_numbers() {
+        let big = read_str("123456789012345678901234567890").unwrap();
+        assert_eq!(big.to_string(), "123456789012345678901234567890");
+        assert!(matches!(
+            read_str("-42").unwrap(),
+            Value::Number(Int::Small(-42))
+        ));
+    }
+
+    #[test]
+    fn floats() {
+        for (input, expected) in [
+            ("1.5", 1.5),
+            ("-0.25", -0.25),
+            ("1e10", 1e10),
+            ("2.5E-3", 2.5e-3),
+            ("##Inf", f64::INFINITY),
+            ("##-Inf", f64::NEG_INFINITY),
+        ] {
+            assert!(matches!(read_str(input).unwrap(), Value::Float(f) if f == expected));
+        }
+        assert!(matches!(read_str("##NaN").unwrap(), Value::Float(f) if f.is_nan()));
+        assert!(read_str("1-2").is_err());
+    }
+
+    #[test]
+    fn ratios() {
+        assert_eq!(read_str("1/3").unwrap().to_string(), "1/3");
+        assert_eq!(read_str("-2/4").unwrap().to_string(), "-1/2");
+        assert!(matches!(
+            read_str("6/3").unwrap(),
+            Value::Number(Int::Small(2))
+        ));
+        assert!(read_str("1/0").is_err());
+        assert!(read_str("1/2/3").is_err());
+    }
+
+    #[test]
+    fn number_literals() {
+        for (input, expected) in [
+            ("0xFF", "255"),
+            ("-0x1f", "-31"),
+            ("0b1010", "10"),
+            ("0o17", "15"),
+            ("2r1011", "11"),
+            ("36rZZ", "1295"),
+            ("1_000_000", "1000000"),
+            ("0xFFFF_FFFF_FFFF_FFFF", "18446744073709551615"),
+            ("1_000.5", "1000.5"),
+            ("1.5e3", "1500.0"),
+            ("-9223372036854775808", "-9223372036854775808"),
+            ("0", "0"),
+            ("-0", "0"),
+            ("0/5", "0"),
+            ("0.5", "0.5"),
+            ("0e1", "0.0"),
+        ] {
+            assert_eq!(read_str(input).unwrap().to_string(), expected);
+        }
+        for input in [
+            "0xFG", "0b102", "37r1", "1__0", "1_", "1_.5", "0x", "1e", "12abc", "017", "-017",
+            "00", "0_1", "01/2", "1/02",
+        ] {
+            assert!(
+                matches!(read_str(input), Err(ParseError::InvalidNumber(n)) if n == input),
+                "{}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn string_escapes() {
+        for (input, expected) in [
+            (r#""a\tb\r\0""#, "a\tb\r\0"),
+            (r#""\u03BB\u{1F600}\u{1b}""#, "\u{3bb}\u{1f600}\u{1b}"),
+        ] {
+            assert!(matches!(read_str(input), Ok(Value::String(s)) if s == expected));
+        }
+        for input in [r#""\u12G4""#, r#""\u{110000}""#, r#""\u{}""#] {
+            assert!(
+                matches!(read_str(input), Err(ParseError::InvalidUnicodeEscape(_))),
+                "{}",
+                input
+            );
+        }
+        for s in [
+            "",
+            "\"'\\\n\t\r\0",
+            "\u{1b}[0m\u{7f}\u{85}",
+            "λ 😀 \u{200b}",
+        ] {
+            let printed = Value::String(s.to_string()).to_string();
+            assert!(
+                matches!(read_str(&printed), Ok(Value::String(read)) if read == s),
+                "{}",
+                printed
+            );
+        }
+    }
+
+    #[test]
+    fn set_literals() {
+        assert!(matches!(read_str("#{1 [2] \"3\"}"), Ok(Value::Set(s, _)) if s.len() == 3));
+        assert!(matches!(read_str("#{}"), Ok(Value::Set(s, _)) if s.is_empty()));
+        assert!(matches!(
+            read_str("#{[1 2] (1 2)}"),
+            Err(ParseError::DuplicateSetElement(e)) if e == "(1 2)"
+        ));
+        assert!(matches!(read_str("#{1"), Err(ParseError::UnexpectedEof)));
+    }
+
+    #[test]
+    fn char_literals() {
+        for (input, expected) in [
+            (r"\a", 'a'),
+            (r"\newline", '\n'),
+            (r"\space", ' '),
+            (r"\λ", 'λ'),
+            (r"\u03BB", 'λ'),
+            (r"\(", '('),
+        ] {
+            assert!(matches!(read_str(input), Ok(Value::Char(c)) if c == expected));
+        }
+        assert_eq!(read_str(r"[\a \tab]").unwrap().to_string(), r"[\a \tab]");
+        for input in [r"\ab", r"\uD800", r"\u12"] {
+            assert!(matches!(
+                read_str(input),
+                Err(ParseError::InvalidCharacter(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn regex_literals() {
+        assert_eq!(
+            read_str(r#"#"a\d+\"b""#).unwrap().to_string(),
+            r#"#"a\d+\"b""#
+        );
+        assert!(matches!(
+            read_str(r#"#"[""#),
+            Err(ParseError::InvalidRegex(_))
+        ));
+        assert!(matches!(
+            read_str(r#"#"abc"#),
+            Err(ParseError::UnterminatedString)
+        ));
+    }
 }
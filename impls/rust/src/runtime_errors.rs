@@ -1,16 +1,25 @@
-use crate::value::Value;
+use std::fmt;
+
+use crate::{printer, value::Value};
 
 pub fn not_a(not: &str, but: &Value) -> Value {
-    Value::String(format!("expected {}, got {}", not, but))
+    Value::String(format!("expected {}, got {}", not, printer::describe(but)))
 }
 
-pub fn out_of_bounds(len: usize, val: i32) -> Value {
+pub fn out_of_bounds(len: usize, val: impl fmt::Display) -> Value {
     Value::String(format!(
         "index out of bounds: length is {}, got {}",
         len, val
     ))
 }
 
+pub fn wrong_arity(name: &str, got: usize) -> Value {
+    Value::String(format!(
+        "wrong number of arguments ({}) passed to {}",
+        got, name
+    ))
+}
+
 pub fn error_to_string_with_ctx(ctx: impl AsRef<str>, e: impl ToString) -> Value {
     Value::String(format!("{}: {}", ctx.as_ref(), e.to_string()))
 }
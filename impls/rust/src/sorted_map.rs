@@ -0,0 +1,395 @@
+//! A persistent sorted map, implemented as an AVL tree.
+//!
+//! The map doesn't store its ordering: every operation that has to compare keys takes
+//! a comparator, which may fail (like a user supplied mal function). All operations
+//! must be passed comparators that order the keys in the same way.
+//! Updates copy the path from the root to the changed node, so old versions stay valid.
+
+use std::{cmp::Ordering, fmt, rc::Rc};
+
+type Link<K, V> = Option<Rc<Node<K, V>>>;
+
+struct Node<K, V> {
+    key: K,
+    value: V,
+    left: Link<K, V>,
+    right: Link<K, V>,
+    height: u8,
+}
+
+fn height<K, V>(link: &Link<K, V>) -> u8 {
+    link.as_ref().map_or(0, |node| node.height)
+}
+
+fn node<K, V>(key: K, value: V, left: Link<K, V>, right: Link<K, V>) -> Rc<Node<K, V>> {
+    let height = 1 + height(&left).max(height(&right));
+    Rc::new(Node {
+        key,
+        value,
+        left,
+        right,
+        height,
+    })
+}
+
+impl<K: Clone, V: Clone> Node<K, V> {
+    /// Like `node`, but rotates if the heights of the subtrees differ by two.
+    fn balanced(key: K, value: V, left: Link<K, V>, right: Link<K, V>) -> Rc<Self> {
+        let (left_height, right_height) = (height(&left), height(&right));
+        if left_height > right_height + 1 {
+            let l = left.unwrap();
+            if height(&l.left) >= height(&l.right) {
+                node(
+                    l.key.clone(),
+                    l.value.clone(),
+                    l.left.clone(),
+                    Some(node(key, value, l.right.clone(), right)),
+                )
+            } else {
+                let lr = l.right.as_ref().unwrap();
+                node(
+                    lr.key.clone(),
+                    lr.value.clone(),
+                    Some(node(
+                        l.key.clone(),
+                        l.value.clone(),
+                        l.left.clone(),
+                        lr.left.clone(),
+                    )),
+                    Some(node(key, value, lr.right.clone(), right)),
+                )
+            }
+        } else if right_height > left_height + 1 {
+            let r = right.unwrap();
+            if height(&r.right) >= height(&r.left) {
+                node(
+                    r.key.clone(),
+                    r.value.clone(),
+                    Some(node(key, value, left, r.left.clone())),
+                    r.right.clone(),
+                )
+            } else {
+                let rl = r.left.as_ref().unwrap();
+                node(
+                    rl.key.clone(),
+                    rl.value.clone(),
+                    Some(node(key, value, left, rl.left.clone())),
+                    Some(node(
+                        r.key.clone(),
+                        r.value.clone(),
+                        rl.right.clone(),
+                        r.right.clone(),
+                    )),
+                )
+            }
+        } else {
+            node(key, value, left, right)
+        }
+    }
+
+    fn with_left(&self, left: Link<K, V>) -> Rc<Self> {
+        Self::balanced(
+            self.key.clone(),
+            self.value.clone(),
+            left,
+            self.right.clone(),
+        )
+    }
+
+    fn with_right(&self, right: Link<K, V>) -> Rc<Self> {
+        Self::balanced(
+            self.key.clone(),
+            self.value.clone(),
+            self.left.clone(),
+            right,
+        )
+    }
+}
+
+/// Returns the new subtree and whether a new key was added.
+fn insert<K: Clone, V: Clone, E>(
+    link: &Link<K, V>,
+    key: K,
+    value: V,
+    cmp: &mut impl FnMut(&K, &K) -> Result<Ordering, E>,
+) -> Result<(Rc<Node<K, V>>, bool), E> {
+    let n = match link {
+        Some(n) => n,
+        None => return Ok((node(key, value, None, None), true)),
+    };
+    Ok(match cmp(&key, &n.key)? {
+        Ordering::Less => {
+            let (left, added) = insert(&n.left, key, value, cmp)?;
+            (n.with_left(Some(left)), added)
+        }
+        Ordering::Greater => {
+            let (right, added) = insert(&n.right, key, value, cmp)?;
+            (n.with_right(Some(right)), added)
+        }
+        // keep the original key, like Clojure
+        Ordering::Equal => (
+            node(n.key.clone(), value, n.left.clone(), n.right.clone()),
+            false,
+        ),
+    })
+}
+
+/// Returns the new subtree, or `None` if the key wasn't found.
+fn remove<K: Clone, V: Clone, E>(
+    link: &Link<K, V>,
+    key: &K,
+    cmp: &mut impl FnMut(&K, &K) -> Result<Ordering, E>,
+) -> Result<Option<Link<K, V>>, E> {
+    let n = match link {
+        Some(n) => n,
+        None => return Ok(None),
+    };
+    Ok(match cmp(key, &n.key)? {
+        Ordering::Less => remove(&n.left, key, cmp)?.map(|left| Some(n.with_left(left))),
+        Ordering::Greater => remove(&n.right, key, cmp)?.map(|right| Some(n.with_right(right))),
+        Ordering::Equal => Some(match &n.right {
+            None => n.left.clone(),
+            Some(right) => {
+                let (key, value, right) = remove_min(right);
+                Some(Node::balanced(key, value, n.left.clone(), right))
+            }
+        }),
+    })
+}
+
+fn remove_min<K: Clone, V: Clone>(n: &Rc<Node<K, V>>) -> (K, V, Link<K, V>) {
+    match &n.left {
+        None => (n.key.clone(), n.value.clone(), n.right.clone()),
+        Some(left) => {
+            let (key, value, left) = remove_min(left);
+            (key, value, Some(n.with_left(left)))
+        }
+    }
+}
+
+/// An immutable map that keeps its keys sorted, with cheap copies, `insert` and `remove`.
+pub struct PersistentSortedMap<K, V> {
+    root: Link<K, V>,
+    len: usize,
+}
+
+// not derived, because that would require `K: Clone` and `V: Clone`
+impl<K, V> Clone for PersistentSortedMap<K, V> {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+            len: self.len,
+        }
+    }
+}
+
+impl<K: Clone, V: Clone> PersistentSortedMap<K, V> {
+    pub fn new() -> Self {
+        Self { root: None, len: 0 }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn get_key_value<E>(
+        &self,
+        key: &K,
+        cmp: &mut impl FnMut(&K, &K) -> Result<Ordering, E>,
+    ) -> Result<Option<(&K, &V)>, E> {
+        let mut link = &self.root;
+        while let Some(n) = link {
+            link = match cmp(key, &n.key)? {
+                Ordering::Less => &n.left,
+                Ordering::Greater => &n.right,
+                Ordering::Equal => return Ok(Some((&n.key, &n.value))),
+            };
+        }
+        Ok(None)
+    }
+
+    pub fn insert<E>(
+        &mut self,
+        key: K,
+        value: V,
+        cmp: &mut impl FnMut(&K, &K) -> Result<Ordering, E>,
+    ) -> Result<(), E> {
+        let (root, added) = insert(&self.root, key, value, cmp)?;
+        self.root = Some(root);
+        if added {
+            self.len += 1;
+        }
+        Ok(())
+    }
+
+    /// Returns whether the key was present.
+    pub fn remove<E>(
+        &mut self,
+        key: &K,
+        cmp: &mut impl FnMut(&K, &K) -> Result<Ordering, E>,
+    ) -> Result<bool, E> {
+        match remove(&self.root, key, cmp)? {
+            Some(root) => {
+                self.root = root;
+                self.len -= 1;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Iterates, in ascending or descending order, over the entries starting at the
+    /// first key that is after `key` in that order (or equal to it, if `inclusive`).
+    pub fn range_from<E>(
+        &self,
+        key: &K,
+        inclusive: bool,
+        ascending: bool,
+        cmp: &mut impl FnMut(&K, &K) -> Result<Ordering, E>,
+    ) -> Result<Iter<'_, K, V>, E> {
+        let after = if ascending {
+            Ordering::Greater
+        } else {
+            Ordering::Less
+        };
+        let mut stack = Vec::new();
+        let mut link = &self.root;
+        while let Some(n) = link {
+            let ordering = cmp(&n.key, key)?;
+            let (towards_start, towards_end) = if ascending {
+                (&n.left, &n.right)
+            } else {
+                (&n.right, &n.left)
+            };
+            if ordering == after || (inclusive && ordering == Ordering::Equal) {
+                stack.push(&**n);
+                link = towards_start;
+            } else {
+                link = towards_end;
+            }
+        }
+        Ok(Iter { stack, ascending })
+    }
+}
+
+impl<K, V> PersistentSortedMap<K, V> {
+    /// Iterates over the entries in ascending order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            ascending: true,
+        };
+        iter.push_path(&self.root);
+        iter
+    }
+
+    /// Iterates over the entries in descending order.
+    pub fn iter_rev(&self) -> Iter<'_, K, V> {
+        let mut iter = Iter {
+            stack: Vec::new(),
+            ascending: false,
+        };
+        iter.push_path(&self.root);
+        iter
+    }
+}
+
+impl<K: Clone, V: Clone> Default for PersistentSortedMap<K, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug> fmt::Debug for PersistentSortedMap<K, V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+pub struct Iter<'a, K, V> {
+    // the next node is on top, with the nodes it comes before below it
+    stack: Vec<&'a Node<K, V>>,
+    ascending: bool,
+}
+
+impl<'a, K, V> Iter<'a, K, V> {
+    /// Pushes the path to the first node of the subtree in iteration order.
+    fn push_path(&mut self, mut link: &'a Link<K, V>) {
+        while let Some(n) = link {
+            self.stack.push(n);
+            link = if self.ascending { &n.left } else { &n.right };
+        }
+    }
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<(&'a K, &'a V)> {
+        let n = self.stack.pop()?;
+        self.push_path(if self.ascending { &n.right } else { &n.left });
+        Some((&n.key, &n.value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use super::*;
+
+    fn cmp(a: &i32, b: &i32) -> Result<Ordering, Infallible> {
+        Ok(a.cmp(b))
+    }
+
+    #[test]
+    fn insert_remove() {
+        let mut map = PersistentSortedMap::new();
+        for i in (0..1000).map(|i| (i * 7919) % 1000) {
+            map.insert(i, -i, &mut cmp).unwrap();
+        }
+        assert_eq!(map.len(), 1000);
+        assert!(map.iter().map(|(k, _)| *k).eq(0..1000));
+        assert!(map.iter_rev().map(|(k, _)| *k).eq((0..1000).rev()));
+        assert_eq!(map.get_key_value(&5, &mut cmp).unwrap(), Some((&5, &-5)));
+        // the height of an AVL tree is at most ~1.44 log2(n)
+        assert!(height(&map.root) <= 14);
+        let snapshot = map.clone();
+        for i in (0..1000).step_by(2) {
+            assert!(map.remove(&i, &mut cmp).unwrap());
+        }
+        assert!(!map.remove(&0, &mut cmp).unwrap());
+        assert!(map.iter().map(|(k, _)| *k).eq((1..1000).step_by(2)));
+        assert_eq!(snapshot.len(), 1000);
+        assert!(height(&map.root) <= 13);
+    }
+
+    #[test]
+    fn ranges() {
+        let map: PersistentSortedMap<i32, ()> =
+            (0..100)
+                .step_by(10)
+                .fold(PersistentSortedMap::new(), |mut map, i| {
+                    map.insert(i, (), &mut cmp).unwrap();
+                    map
+                });
+        let keys = |iter: Iter<'_, i32, ()>| iter.map(|(k, _)| *k).collect::<Vec<_>>();
+        assert_eq!(
+            keys(map.range_from(&70, true, true, &mut cmp).unwrap()),
+            [70, 80, 90]
+        );
+        assert_eq!(
+            keys(map.range_from(&70, false, true, &mut cmp).unwrap()),
+            [80, 90]
+        );
+        assert_eq!(
+            keys(map.range_from(&25, true, false, &mut cmp).unwrap()),
+            [20, 10, 0]
+        );
+        assert_eq!(
+            keys(map.range_from(&20, false, false, &mut cmp).unwrap()),
+            [10, 0]
+        );
+        assert!(keys(map.range_from(&95, true, true, &mut cmp).unwrap()).is_empty());
+    }
+}
@@ -0,0 +1,159 @@
+//! Interned names of symbols and keywords.
+//!
+//! Each distinct name is stored once per thread, so names compare by pointer. The hash of
+//! the text is computed once when a name is interned, so that hashing is cheap too, and
+//! maps order names the same way in every run. Interned names are never freed.
+
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    fmt,
+    hash::{Hash, Hasher},
+    ops::Deref,
+    rc::Rc,
+};
+
+use rustc_hash::{FxHashMap, FxHasher};
+
+thread_local! {
+    static NAMES: RefCell<FxHashMap<Rc<str>, u64>> = RefCell::new(FxHashMap::default());
+}
+
+#[derive(Clone)]
+pub struct Symbol {
+    name: Rc<str>,
+    hash: u64,
+}
+
+impl Symbol {
+    pub fn new(name: &str) -> Self {
+        NAMES.with(|names| {
+            let mut names = names.borrow_mut();
+            if let Some((interned, hash)) = names.get_key_value(name) {
+                return Symbol {
+                    name: interned.clone(),
+                    hash: *hash,
+                };
+            }
+            let interned: Rc<str> = name.into();
+            let hash = content_hash(name);
+            names.insert(interned.clone(), hash);
+            Symbol {
+                name: interned,
+                hash,
+            }
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.name
+    }
+}
+
+fn content_hash(name: &str) -> u64 {
+    let mut hasher = FxHasher::default();
+    name.hash(&mut hasher);
+    hasher.finish()
+}
+
+impl Deref for Symbol {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.name
+    }
+}
+
+impl From<&str> for Symbol {
+    fn from(name: &str) -> Self {
+        Symbol::new(name)
+    }
+}
+
+impl From<String> for Symbol {
+    fn from(name: String) -> Self {
+        Symbol::new(&name)
+    }
+}
+
+impl PartialEq for Symbol {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.name, &other.name)
+    }
+}
+
+impl Eq for Symbol {}
+
+impl PartialEq<str> for Symbol {
+    fn eq(&self, other: &str) -> bool {
+        self.as_str() == other
+    }
+}
+
+impl PartialEq<&str> for Symbol {
+    fn eq(&self, other: &&str) -> bool {
+        self.as_str() == *other
+    }
+}
+
+impl Hash for Symbol {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.hash.hash(state)
+    }
+}
+
+/// Orders names alphabetically, not by address, so that sorted collections print the same
+/// way in every run.
+impl Ord for Symbol {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.as_str().cmp(other.as_str())
+    }
+}
+
+impl PartialOrd for Symbol {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl fmt::Display for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+impl fmt::Debug for Symbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&*self.name, f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names_are_interned() {
+        let a = Symbol::new("abc");
+        let b = Symbol::from(String::from("abc"));
+        assert!(Rc::ptr_eq(&a.name, &b.name));
+        assert_eq!(a, b);
+        assert_ne!(a, Symbol::new("abd"));
+        assert!(a < Symbol::new("abd"));
+        assert_eq!(a, "abc");
+        assert_eq!(a.to_string(), "abc");
+    }
+
+    #[test]
+    fn hashes_depend_on_the_text_only() {
+        let hash = |name: &str| {
+            let mut hasher = FxHasher::default();
+            Symbol::new(name).hash(&mut hasher);
+            hasher.finish()
+        };
+        let mut hasher = FxHasher::default();
+        content_hash("abc").hash(&mut hasher);
+        assert_eq!(hash("abc"), hasher.finish());
+        assert_ne!(hash("abc"), hash("abd"));
+    }
+}
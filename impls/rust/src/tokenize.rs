@@ -1,16 +1,39 @@
-use crate::reader::{ParseError, ParseResult};
+use std::borrow::Cow;
+
+use num_bigint::BigInt;
+use num_rational::BigRational;
+
+use crate::{
+    number::Int,
+    reader::{ParseError, ParseResult},
+    value::Value,
+};
+
+/// Characters that are written by name in character literals, like `\newline`.
+pub const CHAR_NAMES: [(char, &str); 6] = [
+    ('\n', "newline"),
+    (' ', "space"),
+    ('\t', "tab"),
+    ('\r', "return"),
+    ('\u{8}', "backspace"),
+    ('\u{c}', "formfeed"),
+];
 
 #[derive(PartialEq, Eq)]
 pub enum Token {
     String(String),
     Ident(String),
     Keyword(String),
-    Number(String),
+    Number(Value),
+    SymbolicValue(String),
+    Regex(String),
+    Char(char),
     LeftParen,
     RightParen,
     LeftBracket,
     RightBracket,
     LeftBrace,
+    HashLeftBrace,
     RightBrace,
     SingleQuote,
     Backtick,
@@ -55,8 +78,12 @@ impl<'a> Tokenizer<'a> {
                 match self.advance() {
                     None => return Err(ParseError::UnterminatedString),
                     Some('n') => string.push('\n'),
+                    Some('t') => string.push('\t'),
+                    Some('r') => string.push('\r'),
+                    Some('0') => string.push('\0'),
                     Some('\\') => string.push('\\'),
                     Some('"') => string.push('"'),
+                    Some('u') => string.push(self.read_unicode_escape()?),
                     Some(c) => return Err(ParseError::InvalidStringEscape(c)),
                 }
                 continue;
@@ -69,13 +96,84 @@ impl<'a> Tokenizer<'a> {
         Err(ParseError::UnterminatedString)
     }
 
+    /// Reads the code point of a `\uXXXX` or `\u{X...}` escape, after the `\u`.
+    fn read_unicode_escape(&mut self) -> ParseResult<char> {
+        let start = self.current;
+        let hex = if self.peek() == Some('{') {
+            self.advance();
+            loop {
+                match self.advance() {
+                    Some('}') => break &self.input[start + 1..self.current - 1],
+                    Some(c) if c.is_ascii_hexdigit() => {}
+                    Some(_) => return Err(self.invalid_unicode_escape(start)),
+                    None => return Err(ParseError::UnterminatedString),
+                }
+            }
+        } else {
+            for _ in 0..4 {
+                match self.advance() {
+                    Some(c) if c.is_ascii_hexdigit() => {}
+                    Some(_) => return Err(self.invalid_unicode_escape(start)),
+                    None => return Err(ParseError::UnterminatedString),
+                }
+            }
+            &self.input[start..self.current]
+        };
+        u32::from_str_radix(hex, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| self.invalid_unicode_escape(start))
+    }
+
+    fn invalid_unicode_escape(&self, start: usize) -> ParseError {
+        ParseError::InvalidUnicodeEscape(self.input[start..self.current].to_string())
+    }
+
+    /// Reads the source of a regex literal. Escapes are kept as they are,
+    /// so that they can be interpreted by the regex engine.
+    fn read_regex(&mut self) -> ParseResult<String> {
+        self.advance();
+        let start = self.current;
+        while let Some(c) = self.advance() {
+            match c {
+                '\\' if self.advance().is_none() => break,
+                '"' => return Ok(self.input[start..self.current - 1].to_string()),
+                _ => {}
+            }
+        }
+        Err(ParseError::UnterminatedString)
+    }
+
+    /// Reads a character literal: `\a`, a named character like `\newline`,
+    /// or a unicode escape like `\u03BB`.
+    fn read_char(&mut self) -> ParseResult<char> {
+        // the first character is always part of the literal, even if it is punctuation
+        self.advance().ok_or(ParseError::UnexpectedEof)?;
+        self.read_ident();
+        let text = &self.input[self.start + 1..self.current];
+        let mut chars = text.chars();
+        let first = chars.next().unwrap();
+        if chars.as_str().is_empty() {
+            return Ok(first);
+        }
+        if let Some((c, _)) = CHAR_NAMES.iter().find(|(_, name)| *name == text) {
+            return Ok(*c);
+        }
+        match text.strip_prefix('u') {
+            Some(hex) if hex.len() == 4 => u32::from_str_radix(hex, 16)
+                .ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| ParseError::InvalidCharacter(text.to_string())),
+            _ => Err(ParseError::InvalidCharacter(text.to_string())),
+        }
+    }
+
     fn read_keyword(&mut self) -> ParseResult<String> {
         self.advance();
-        let mut ident = self.read_ident();
+        let ident = self.read_ident();
         if ident.is_empty() {
             Err(ParseError::EmptyKeyword)
         } else {
-            ident.insert(0, char::from_u32(0x29E).unwrap());
             Ok(ident)
         }
     }
@@ -106,19 +204,21 @@ impl<'a> Tokenizer<'a> {
         self.input[self.start..self.current].to_string()
     }
 
-    fn read_num(&mut self) -> ParseResult<String> {
-        let mut is_first = true;
+    fn read_num(&mut self) -> ParseResult<Value> {
         while let Some(char) = self.peek() {
             if Self::is_punct(char) || Self::is_whitespace(char) {
                 break;
             }
-            if !matches!(char, '0'..='9') && (!is_first && char == '-') {
-                return Err(ParseError::InvalidNumber);
-            }
             self.advance();
-            is_first = false;
         }
-        Ok(self.input[self.start..self.current].to_string())
+        let text = &self.input[self.start..self.current];
+        parse_number(text).ok_or_else(|| ParseError::InvalidNumber(text.to_string()))
+    }
+
+    fn read_symbolic_value(&mut self) -> String {
+        self.advance();
+        self.start = self.current;
+        self.read_ident()
     }
 
     fn skip_whitespace(&mut self) {
@@ -154,8 +254,17 @@ impl<'a> Tokenizer<'a> {
                 '^' => Token::Hat,
                 '"' => Token::String(self.read_string()?),
                 ':' => Token::Keyword(self.read_keyword()?),
+                '\\' => Token::Char(self.read_char()?),
                 '0'..='9' => Token::Number(self.read_num()?),
                 '-' if matches!(self.peek(), Some('0'..='9')) => Token::Number(self.read_num()?),
+                '#' if matches!(self.peek(), Some('"')) => Token::Regex(self.read_regex()?),
+                '#' if matches!(self.peek(), Some('{')) => {
+                    self.advance();
+                    Token::HashLeftBrace
+                }
+                '#' if matches!(self.peek(), Some('#')) => {
+                    Token::SymbolicValue(self.read_symbolic_value())
+                }
                 c if Self::is_ident(c) => Token::Ident(self.read_ident()),
                 _ => unreachable!("all characters can be idents if nothing else"),
             },
@@ -164,3 +273,99 @@ impl<'a> Tokenizer<'a> {
         Ok(token)
     }
 }
+
+/// Parses a number literal with an optional leading minus:
+/// - integers in decimal (`42`), hex (`0xFF`), binary (`0b1010`), octal (`0o17`)
+///   or an explicit radix between 2 and 36 (`2r1011`, `36rZZ`)
+/// - ratios (`1/3`)
+/// - floats (`1.5`, `1e10`, `2.5E-3`)
+///
+/// Digits may be separated by underscores (`1_000_000`). Decimal integers can't have leading
+/// zeros, so that `017` isn't mistaken for an octal literal.
+fn parse_number(text: &str) -> Option<Value> {
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, text),
+    };
+    let prefixed = |prefixes: [&str; 2]| {
+        prefixes
+            .iter()
+            .find_map(|prefix| unsigned.strip_prefix(prefix))
+    };
+    let value = if let Some(digits) = prefixed(["0x", "0X"]) {
+        Value::Number(parse_int(digits, 16)?)
+    } else if let Some(digits) = prefixed(["0b", "0B"]) {
+        Value::Number(parse_int(digits, 2)?)
+    } else if let Some(digits) = prefixed(["0o", "0O"]) {
+        Value::Number(parse_int(digits, 8)?)
+    } else if let Some((radix, digits)) = unsigned.split_once(['r', 'R']) {
+        let radix = radix
+            .parse()
+            .ok()
+            .filter(|radix| (2..=36).contains(radix))?;
+        Value::Number(parse_int(digits, radix)?)
+    } else if let Some((numer, denom)) = unsigned.split_once('/') {
+        let denom = parse_decimal(denom)?;
+        if denom.is_zero() {
+            return None;
+        }
+        Value::from(BigRational::new(
+            parse_decimal(numer)?.to_big(),
+            denom.to_big(),
+        ))
+    } else if let Some(int) = parse_decimal(unsigned) {
+        Value::Number(int)
+    } else {
+        let float = remove_separators(unsigned, |c| c.is_ascii_digit())?;
+        let is_float_char =
+            |c: char| c.is_ascii_digit() || matches!(c, '.' | 'e' | 'E' | '-' | '+');
+        if !float.starts_with(|c: char| c.is_ascii_digit())
+            || !float.chars().all(is_float_char)
+            || !float.contains(['.', 'e', 'E'])
+        {
+            return None;
+        }
+        Value::Float(float.parse().ok()?)
+    };
+    if negative {
+        (-value).ok()
+    } else {
+        Some(value)
+    }
+}
+
+fn parse_decimal(digits: &str) -> Option<Int> {
+    if digits.len() > 1 && digits.starts_with('0') {
+        return None;
+    }
+    parse_int(digits, 10)
+}
+
+fn parse_int(digits: &str, radix: u32) -> Option<Int> {
+    let digits = remove_separators(digits, |c| c.is_digit(radix))?;
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return None;
+    }
+    match i64::from_str_radix(&digits, radix) {
+        Ok(n) => Some(Int::Small(n)),
+        Err(_) => BigInt::parse_bytes(digits.as_bytes(), radix).map(Int::from),
+    }
+}
+
+/// Removes `_` digit separators, which are only allowed between two digits.
+fn remove_separators(text: &str, is_digit: impl Fn(char) -> bool) -> Option<Cow<'_, str>> {
+    if !text.contains('_') {
+        return Some(Cow::Borrowed(text));
+    }
+    let chars: Vec<char> = text.chars().collect();
+    for (i, c) in chars.iter().enumerate() {
+        if *c == '_' {
+            let before = i.checked_sub(1).map(|i| chars[i]);
+            let after = chars.get(i + 1).copied();
+            if !before.is_some_and(&is_digit) || !after.is_some_and(&is_digit) {
+                return None;
+            }
+        }
+    }
+    Some(Cow::Owned(text.replace('_', "")))
+}
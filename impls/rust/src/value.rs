@@ -1,26 +1,65 @@
 use std::{
+    borrow::Cow,
     cell::RefCell,
     cmp::Ordering,
     fmt,
-    ops::{Add, Div, Mul, Sub},
+    hash::{Hash, Hasher},
+    ops::{Add, Div, Mul, Neg, Sub},
     rc::Rc,
 };
 
-use rustc_hash::FxHashMap;
+use num_rational::BigRational;
+use num_traits::{ToPrimitive, Zero};
+use rustc_hash::FxHasher;
 use rustyline::Editor;
 
 use crate::{
+    bytecode::Function,
     env::Env,
+    hash_map::PersistentHashMap,
+    hash_set::PersistentHashSet,
+    lazy_seq::{self, LazySeq},
+    number::Int,
+    pattern::Pattern,
     runtime_errors::{self, RuntimeResult},
+    sorted_map::PersistentSortedMap,
+    symbol::Symbol,
+    vector::{self, PersistentVector},
 };
 #[derive(Clone, Debug)]
 pub struct Closure {
-    pub ast: Value,
-    pub params: Vec<Value>,
-    pub env: Rc<RefCell<Env>>,
+    pub function: Rc<Function>,
+    /// The values of the locals of enclosing functions that the function uses.
+    pub upvalues: Vec<Value>,
     pub is_macro: bool,
 }
 
+/// Iterates over the elements of a list or a vector.
+pub enum ListOrVecIter<'a> {
+    List(std::slice::Iter<'a, Value>),
+    Vec(vector::Iter<'a, Value>),
+}
+
+impl<'a> Iterator for ListOrVecIter<'a> {
+    type Item = &'a Value;
+
+    fn next(&mut self) -> Option<&'a Value> {
+        match self {
+            ListOrVecIter::List(iter) => iter.next(),
+            ListOrVecIter::Vec(iter) => iter.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            ListOrVecIter::List(iter) => iter.size_hint(),
+            ListOrVecIter::Vec(iter) => iter.size_hint(),
+        }
+    }
+}
+
+impl ExactSizeIterator for ListOrVecIter<'_> {}
+
 #[derive(Clone)]
 pub struct MalFnPtr(pub fn(std::vec::IntoIter<Value>, Rc<RefCell<Env>>) -> RuntimeResult<Value>);
 
@@ -38,30 +77,76 @@ pub enum HostFn {
     ReadLine(Rc<RefCell<Editor<()>>>),
 }
 
+impl HostFn {
+    fn variant_rank(&self) -> u8 {
+        match self {
+            HostFn::ByPtr(_) => 0,
+            HostFn::Eval(_) => 1,
+            HostFn::Apply => 2,
+            HostFn::ReadLine(_) => 3,
+        }
+    }
+}
+
 impl PartialEq for HostFn {
     fn eq(&self, other: &Self) -> bool {
-        match (self, other) {
-            (HostFn::ByPtr(a), HostFn::ByPtr(b)) => std::ptr::eq(&a, &b),
-            (HostFn::Eval(_), HostFn::Eval(_)) => true,
-            (HostFn::Apply, HostFn::Apply) => true,
-            _ => false,
-        }
+        self.cmp(other) == Ordering::Equal
     }
 }
 
 impl Eq for HostFn {}
 
+impl Hash for HostFn {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.variant_rank().hash(state);
+        if let HostFn::ByPtr(f) = self {
+            (f.0 as usize).hash(state);
+        }
+    }
+}
+
+impl PartialOrd for HostFn {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for HostFn {
+    fn cmp(&self, other: &Self) -> Ordering {
+        match (self, other) {
+            (HostFn::ByPtr(a), HostFn::ByPtr(b)) => (a.0 as usize).cmp(&(b.0 as usize)),
+            _ => self.variant_rank().cmp(&other.variant_rank()),
+        }
+    }
+}
+
 pub type Meta = Box<Value>;
 
+/// The entries of a sorted map (or the elements of a sorted set, with `()` values),
+/// and the function they are sorted by. Without a comparator, they are sorted by `compare`.
+#[derive(Clone, Debug)]
+pub struct Sorted<V> {
+    pub entries: PersistentSortedMap<Value, V>,
+    pub comparator: Option<Box<Value>>,
+}
+
 #[derive(Clone, Debug)]
 pub enum Value {
     List(Vec<Value>, Meta),
-    Vec(Vec<Value>, Meta),
-    Map(FxHashMap<String, Value>, Meta),
-    Number(i32),
-    Symbol(String),
-    Keyword(String),
+    Vec(PersistentVector<Value>, Meta),
+    Map(PersistentHashMap<Value, Value>, Meta),
+    Set(PersistentHashSet<Value>, Meta),
+    SortedMap(Sorted<Value>, Meta),
+    SortedSet(Sorted<()>, Meta),
+    LazySeq(Rc<LazySeq>, Meta),
+    Number(Int),
+    Ratio(Rc<BigRational>),
+    Float(f64),
+    Symbol(Symbol),
+    Keyword(Symbol),
     String(String),
+    Char(char),
+    Regex(Rc<Pattern>),
     HostFn(HostFn, Meta),
     Closure(Rc<Closure>, Meta),
     Nil,
@@ -70,19 +155,7 @@ pub enum Value {
 }
 
 impl Value {
-    pub fn into_hash_map_key(self) -> Result<String, Self> {
-        match self {
-            Value::Keyword(s) | Value::String(s) => Ok(s),
-            _ => Err(self),
-        }
-    }
-    pub fn as_hash_map_key(&self) -> RuntimeResult<&str> {
-        match self {
-            Value::Keyword(s) | Value::String(s) => Ok(s),
-            v => Err(runtime_errors::not_a("hash map key (keyword or string)", v)),
-        }
-    }
-    pub fn try_into_env_map_key(self) -> RuntimeResult<String> {
+    pub fn try_into_env_map_key(self) -> RuntimeResult<Symbol> {
         match self {
             Value::Symbol(s) => Ok(s),
             _ => Err(runtime_errors::not_a("map key (symbol)", &self)),
@@ -95,18 +168,83 @@ impl Value {
             _ => unreachable!(),
         }
     }
-    pub fn try_as_list_or_vec(&self) -> Option<&[Value]> {
+    pub fn try_iter_list_or_vec(&self) -> Option<ListOrVecIter<'_>> {
+        match self {
+            Value::List(l, _) => Some(ListOrVecIter::List(l.iter())),
+            Value::Vec(v, _) => Some(ListOrVecIter::Vec(v.iter())),
+            _ => None,
+        }
+    }
+    /// A map entry as it appears in sequences: a `[key value]` vector.
+    pub fn map_entry(key: &Value, value: &Value) -> Value {
+        Value::Vec(
+            vec![key.clone(), value.clone()].into(),
+            Box::new(Value::Nil),
+        )
+    }
+    /// Iterates over the entries of a hash map or a sorted map.
+    pub fn try_iter_map(&self) -> Option<Box<dyn Iterator<Item = (&Value, &Value)> + '_>> {
+        match self {
+            Value::Map(map, _) => Some(Box::new(map.iter())),
+            Value::SortedMap(map, _) => Some(Box::new(map.entries.iter())),
+            _ => None,
+        }
+    }
+    /// Iterates over the elements of a hash set or a sorted set.
+    pub fn try_iter_set(&self) -> Option<Box<dyn Iterator<Item = &Value> + '_>> {
         match self {
-            Value::List(l, _) | Value::Vec(l, _) => Some(l),
+            Value::Set(set, _) => Some(Box::new(set.iter())),
+            Value::SortedSet(set, _) => Some(Box::new(set.entries.iter().map(|(v, _)| v))),
             _ => None,
         }
     }
+    /// The number of entries of a map or elements of a set.
+    fn count(&self) -> usize {
+        match self {
+            Value::Map(map, _) => map.len(),
+            Value::SortedMap(map, _) => map.entries.len(),
+            Value::Set(set, _) => set.len(),
+            Value::SortedSet(set, _) => set.entries.len(),
+            _ => unreachable!("only used for maps and sets"),
+        }
+    }
     pub fn try_into_list_or_vec(self) -> RuntimeResult<Vec<Value>> {
         match self {
-            Value::List(l, _) | Value::Vec(l, _) => Ok(l),
+            Value::List(l, _) => Ok(l),
+            Value::Vec(v, _) => Ok(v.iter().cloned().collect()),
+            seq @ Value::LazySeq(_, _) => SeqIter::new(seq)?.collect(),
             v => Err(runtime_errors::not_a("list or vec", &v)),
         }
     }
+    /// Realizes all lazy sequences in this value, including nested ones, so that
+    /// printing or comparing it can't fail.
+    pub fn realize(&self) -> RuntimeResult<()> {
+        match self {
+            Value::List(_, _) | Value::Vec(_, _) => {
+                for v in self.try_iter_list_or_vec().unwrap() {
+                    v.realize()?;
+                }
+            }
+            Value::Map(_, _) | Value::SortedMap(_, _) => {
+                for (k, v) in self.try_iter_map().unwrap() {
+                    k.realize()?;
+                    v.realize()?;
+                }
+            }
+            Value::Set(_, _) | Value::SortedSet(_, _) => {
+                for v in self.try_iter_set().unwrap() {
+                    v.realize()?;
+                }
+            }
+            Value::LazySeq(_, _) => {
+                for v in SeqIter::new(self.clone())? {
+                    v?.realize()?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
     /*pub fn value_to_string(&self, readably: bool) -> String {
         let mut buf = String::new();
         pr_str(self, &mut buf, readably).unwrap();
@@ -124,110 +262,513 @@ impl Value {
             v => Err(runtime_errors::not_a("string", &v)),
         }
     }
-    pub fn try_as_number(&self) -> Option<i32> {
-        match self {
-            Value::Number(n) => Some(*n),
-            _ => None,
-        }
-    }
-    /*pub fn try_as_map(&self) -> RuntimeResult<&FxHashMap<String, Value>> {
+    /*pub fn try_as_map(&self) -> RuntimeResult<&PersistentHashMap<Value, Value>> {
         match self {
             Value::Map(m, _) => Ok(m),
             v => Err(runtime_errors::not_a("hash map", v)),
         }
     }*/
-    pub fn try_into_map(self) -> RuntimeResult<FxHashMap<String, Value>> {
+    pub fn try_into_map(self) -> RuntimeResult<PersistentHashMap<Value, Value>> {
         match self {
             Value::Map(m, _) => Ok(m),
             v => Err(runtime_errors::not_a("hash map", &v)),
         }
     }
-    fn deref_atom_recursively(mut self) -> Self {
-        while let Value::Atom(v) = self {
-            self = v.borrow().clone();
+    pub fn try_into_int(self) -> RuntimeResult<Int> {
+        match self {
+            Value::Number(n) => Ok(n),
+            v => Err(runtime_errors::not_a("integer", &v)),
+        }
+    }
+    pub fn is_number(&self) -> bool {
+        matches!(self, Value::Number(_) | Value::Ratio(_) | Value::Float(_))
+    }
+    pub fn try_as_f64(&self) -> Option<f64> {
+        match self {
+            Value::Number(n) => Some(n.to_f64()),
+            Value::Ratio(r) => r.to_f64(),
+            Value::Float(f) => Some(*f),
+            _ => None,
+        }
+    }
+}
+
+/// Sequence operations, shared by every seqable value: nil, lists, vectors, lazy sequences,
+/// strings (as characters), maps (as `[key value]` entries) and sets. Collection builtins
+/// go through these, so a new collection type only needs a case in `SeqIter::new`, and in
+/// `counted_len` if it knows its length without iterating.
+impl Value {
+    /// The number of elements of collections that don't have to be iterated to count them.
+    fn counted_len(&self) -> Option<usize> {
+        match self {
+            Value::Nil => Some(0),
+            Value::List(l, _) => Some(l.len()),
+            Value::Vec(v, _) => Some(v.len()),
+            Value::String(s) => Some(s.chars().count()),
+            Value::Map(_, _)
+            | Value::SortedMap(_, _)
+            | Value::Set(_, _)
+            | Value::SortedSet(_, _) => Some(self.count()),
+            _ => None,
+        }
+    }
+    pub fn seq_count(&self) -> RuntimeResult<usize> {
+        match self.counted_len() {
+            Some(len) => Ok(len),
+            None => SeqIter::new(self.clone())?.try_fold(0, |n, v| v.map(|_| n + 1)),
+        }
+    }
+    pub fn seq_is_empty(&self) -> RuntimeResult<bool> {
+        match self.counted_len() {
+            Some(len) => Ok(len == 0),
+            None => Ok(SeqIter::new(self.clone())?.next().transpose()?.is_none()),
+        }
+    }
+    /// All elements, realizing the sequence if it's lazy.
+    pub fn seq_elements(self) -> RuntimeResult<Vec<Value>> {
+        SeqIter::new(self)?.collect()
+    }
+    /// The first element, or nil if the sequence is empty.
+    pub fn seq_first(self) -> RuntimeResult<Value> {
+        Ok(SeqIter::new(self)?
+            .next()
+            .transpose()?
+            .unwrap_or(Value::Nil))
+    }
+    /// Everything but the first element, or an empty list if there is nothing left.
+    pub fn seq_rest(self) -> RuntimeResult<Value> {
+        let mut iter = SeqIter::new(self)?;
+        iter.next().transpose()?;
+        Ok(match iter.into_seq() {
+            Value::Nil => Value::List(Vec::new(), Box::new(Value::Nil)),
+            rest => rest,
+        })
+    }
+    pub fn seq_nth(self, index: &Int) -> RuntimeResult<Value> {
+        let i = index.to_usize();
+        if let Value::Vec(v, _) = &self {
+            return i
+                .and_then(|i| v.get(i))
+                .cloned()
+                .ok_or_else(|| runtime_errors::out_of_bounds(v.len(), index));
+        }
+        let mut len = 0;
+        for v in SeqIter::new(self)? {
+            let v = v?;
+            if Some(len) == i {
+                return Ok(v);
+            }
+            len += 1;
+        }
+        Err(runtime_errors::out_of_bounds(len, index))
+    }
+    /// The elements as a list or lazy sequence, or nil if there are none.
+    pub fn seq(self) -> RuntimeResult<Value> {
+        if self.seq_is_empty()? {
+            return Ok(Value::Nil);
+        }
+        match self {
+            Value::List(l, _) => Ok(Value::List(l, Box::new(Value::Nil))),
+            Value::LazySeq(seq, _) => Ok(Value::LazySeq(seq, Box::new(Value::Nil))),
+            coll => Ok(Value::List(coll.seq_elements()?, Box::new(Value::Nil))),
+        }
+    }
+}
+
+/// Iterates over the elements of a list, vector, lazy sequence or nil, realizing
+/// lazy sequences as it goes. Only the current position is kept alive, so iterating
+/// over a long sequence that isn't referenced elsewhere runs in constant memory.
+///
+/// Strings (as characters), maps (as `[key value]` entries) and sets are iterated
+/// over a list of their elements.
+pub enum SeqIter {
+    List(std::vec::IntoIter<Value>),
+    Vec(PersistentVector<Value>, usize),
+    Seq(Value),
+}
+
+impl SeqIter {
+    pub fn new(coll: Value) -> RuntimeResult<Self> {
+        match coll {
+            Value::List(l, _) => Ok(SeqIter::List(l.into_iter())),
+            Value::Vec(v, _) => Ok(SeqIter::Vec(v, 0)),
+            seq @ (Value::LazySeq(_, _) | Value::Nil) => Ok(SeqIter::Seq(seq)),
+            Value::String(s) => Ok(SeqIter::List(
+                s.chars().map(Value::Char).collect::<Vec<_>>().into_iter(),
+            )),
+            map @ (Value::Map(_, _) | Value::SortedMap(_, _)) => Ok(SeqIter::List(
+                map.try_iter_map()
+                    .unwrap()
+                    .map(|(k, v)| Value::map_entry(k, v))
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            )),
+            set @ (Value::Set(_, _) | Value::SortedSet(_, _)) => Ok(SeqIter::List(
+                set.try_iter_set()
+                    .unwrap()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+            )),
+            v => Err(runtime_errors::not_a("sequence", &v)),
+        }
+    }
+
+    /// The rest of the sequence, without realizing any more of it.
+    pub fn into_seq(self) -> Value {
+        match self {
+            SeqIter::List(l) => Value::List(l.collect(), Box::new(Value::Nil)),
+            SeqIter::Vec(v, i) => {
+                Value::List(v.iter().skip(i).cloned().collect(), Box::new(Value::Nil))
+            }
+            SeqIter::Seq(seq) => seq,
+        }
+    }
+}
+
+impl Iterator for SeqIter {
+    type Item = RuntimeResult<Value>;
+
+    fn next(&mut self) -> Option<RuntimeResult<Value>> {
+        match self {
+            SeqIter::List(l) => l.next().map(Ok),
+            SeqIter::Vec(v, i) => {
+                let next = v.get(*i).cloned()?;
+                *i += 1;
+                Some(Ok(next))
+            }
+            SeqIter::Seq(Value::LazySeq(seq, _)) => match seq.step() {
+                Ok(Some((first, rest))) => {
+                    *self = match rest {
+                        Value::List(l, _) => SeqIter::List(l.into_iter()),
+                        rest => SeqIter::Seq(rest),
+                    };
+                    Some(Ok(first))
+                }
+                Ok(None) => {
+                    *self = SeqIter::Seq(Value::Nil);
+                    None
+                }
+                Err(e) => {
+                    *self = SeqIter::Seq(Value::Nil);
+                    Some(Err(e))
+                }
+            },
+            SeqIter::Seq(_) => None,
+        }
+    }
+}
+
+/// Ratios are always stored in lowest terms, and ratios with a denominator of one
+/// are demoted to integers, so every rational number has exactly one representation.
+impl From<BigRational> for Value {
+    fn from(r: BigRational) -> Self {
+        if r.is_integer() {
+            Value::Number(r.to_integer().into())
+        } else {
+            Value::Ratio(Rc::new(r))
+        }
+    }
+}
+
+/// Two numeric operands converted to a common representation.
+pub enum NumberPair {
+    Ints(Int, Int),
+    Ratios(BigRational, BigRational),
+    Floats(f64, f64),
+}
+
+impl NumberPair {
+    /// Promotes both operands to the more general of their two types,
+    /// following the tower integer < ratio < float.
+    pub fn coerce(a: &Value, b: &Value) -> RuntimeResult<Self> {
+        fn as_ratio(v: &Value) -> RuntimeResult<BigRational> {
+            match v {
+                Value::Number(n) => Ok(n.to_ratio()),
+                Value::Ratio(r) => Ok(r.as_ref().clone()),
+                v => Err(runtime_errors::not_a("number", v)),
+            }
+        }
+        fn as_f64(v: &Value) -> RuntimeResult<f64> {
+            v.try_as_f64()
+                .ok_or_else(|| runtime_errors::not_a("number", v))
+        }
+        match (a, b) {
+            (Value::Number(a), Value::Number(b)) => Ok(NumberPair::Ints(a.clone(), b.clone())),
+            (Value::Float(_), _) | (_, Value::Float(_)) => {
+                Ok(NumberPair::Floats(as_f64(a)?, as_f64(b)?))
+            }
+            _ => Ok(NumberPair::Ratios(as_ratio(a)?, as_ratio(b)?)),
         }
-        self
     }
 }
 
 impl Add for Value {
-    type Output = Value;
+    type Output = RuntimeResult<Value>;
 
     fn add(self, rhs: Self) -> Self::Output {
-        match (self.deref_atom_recursively(), rhs.deref_atom_recursively()) {
-            (Value::Number(a), Value::Number(b)) => Value::Number(a + b),
-            _ => todo!("value type unsupported"),
-        }
+        Ok(match NumberPair::coerce(&self, &rhs)? {
+            NumberPair::Ints(a, b) => Value::Number(a + b),
+            NumberPair::Ratios(a, b) => Value::from(a + b),
+            NumberPair::Floats(a, b) => Value::Float(a + b),
+        })
     }
 }
 impl Sub for Value {
-    type Output = Value;
+    type Output = RuntimeResult<Value>;
 
     fn sub(self, rhs: Self) -> Self::Output {
-        match (self.deref_atom_recursively(), rhs.deref_atom_recursively()) {
-            (Value::Number(a), Value::Number(b)) => Value::Number(a - b),
-            _ => todo!("value type unsupported"),
-        }
+        Ok(match NumberPair::coerce(&self, &rhs)? {
+            NumberPair::Ints(a, b) => Value::Number(a - b),
+            NumberPair::Ratios(a, b) => Value::from(a - b),
+            NumberPair::Floats(a, b) => Value::Float(a - b),
+        })
     }
 }
 impl Mul for Value {
-    type Output = Value;
+    type Output = RuntimeResult<Value>;
 
     fn mul(self, rhs: Self) -> Self::Output {
-        match (self.deref_atom_recursively(), rhs.deref_atom_recursively()) {
-            (Value::Number(a), Value::Number(b)) => Value::Number(a * b),
-            _ => todo!("value type unsupported"),
-        }
+        Ok(match NumberPair::coerce(&self, &rhs)? {
+            NumberPair::Ints(a, b) => Value::Number(a * b),
+            NumberPair::Ratios(a, b) => Value::from(a * b),
+            NumberPair::Floats(a, b) => Value::Float(a * b),
+        })
     }
 }
 impl Div for Value {
     type Output = RuntimeResult<Value>;
 
     fn div(self, rhs: Self) -> Self::Output {
-        match (self.deref_atom_recursively(), rhs.deref_atom_recursively()) {
-            (Value::Number(a), Value::Number(b)) => {
-                if b == 0 {
-                    Err(runtime_errors::error_to_string("division by zero"))
-                } else {
-                    Ok(Value::Number(a / b))
+        match NumberPair::coerce(&self, &rhs)? {
+            NumberPair::Ints(a, b) => {
+                if b.is_zero() {
+                    return Err(runtime_errors::error_to_string("division by zero"));
                 }
+                Ok(Value::from(a.to_ratio() / b.to_ratio()))
             }
-            _ => todo!("value type unsupported"),
+            NumberPair::Ratios(a, b) => {
+                if b.is_zero() {
+                    return Err(runtime_errors::error_to_string("division by zero"));
+                }
+                Ok(Value::from(a / b))
+            }
+            NumberPair::Floats(a, b) => Ok(Value::Float(a / b)),
         }
     }
 }
-impl PartialOrd for Value {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+impl Neg for Value {
+    type Output = RuntimeResult<Value>;
+
+    fn neg(self) -> Self::Output {
+        match self {
+            Value::Number(n) => Ok(Value::Number(-n)),
+            Value::Ratio(r) => Ok(Value::Ratio(Rc::new(-r.as_ref().clone()))),
+            Value::Float(f) => Ok(Value::Float(-f)),
+            v => Err(runtime_errors::not_a("number", &v)),
+        }
+    }
+}
+
+impl Value {
+    /// Compares two numbers by their mathematical value, so `1`, `1/1` and `1.0` compare equal.
+    /// Returns `None` if either value is not a number or NaN is involved.
+    pub fn numeric_cmp(&self, other: &Self) -> Option<Ordering> {
+        fn as_exact(v: &Value) -> Option<BigRational> {
+            match v {
+                Value::Number(n) => Some(n.to_ratio()),
+                Value::Ratio(r) => Some(r.as_ref().clone()),
+                // infinite floats are handled before we need an exact value
+                Value::Float(f) => BigRational::from_float(*f),
+                _ => None,
+            }
+        }
         match (self, other) {
             (Value::Number(a), Value::Number(b)) => Some(a.cmp(b)),
-            _ => None,
+            (Value::Float(a), Value::Float(b)) => a.partial_cmp(b),
+            (Value::Float(f), _) if f.is_nan() => None,
+            (_, Value::Float(f)) if f.is_nan() => None,
+            (Value::Float(f), n) if f.is_infinite() && n.is_number() => Some(f.total_cmp(&0.0)),
+            (n, Value::Float(f)) if f.is_infinite() && n.is_number() => Some(0f64.total_cmp(f)),
+            (a, b) => Some(as_exact(a)?.cmp(&as_exact(b)?)),
+        }
+    }
+
+    /// Orders values of different types: nil < booleans < numbers < characters < strings
+    /// < symbols < keywords < lists and vectors < maps < sets < regexes < functions.
+    fn type_rank(&self) -> u8 {
+        match self {
+            Value::Nil => 0,
+            Value::Bool(_) => 1,
+            Value::Number(_) | Value::Ratio(_) | Value::Float(_) => 2,
+            Value::Char(_) => 3,
+            Value::String(_) => 4,
+            Value::Symbol(_) => 5,
+            Value::Keyword(_) => 6,
+            Value::List(_, _) | Value::Vec(_, _) | Value::LazySeq(_, _) => 7,
+            Value::Map(_, _) | Value::SortedMap(_, _) => 8,
+            Value::Set(_, _) | Value::SortedSet(_, _) => 9,
+            Value::Regex(_) => 10,
+            Value::HostFn(_, _) => 11,
+            Value::Closure(_, _) => 12,
+            Value::Atom(a) => a.borrow().type_rank(),
+        }
+    }
+
+    /// Numbers of different types that are numerically equal aren't `=`, so they are ordered
+    /// integer < ratio < float to keep the total order consistent with equality.
+    fn number_rank(&self) -> u8 {
+        match self {
+            Value::Number(_) => 0,
+            Value::Ratio(_) => 1,
+            _ => 2,
         }
     }
 }
 
+/// A total order over all values, consistent with `=`.
+/// Sequences are compared lexicographically, maps by their entries sorted by key,
+/// and NaN is sorted after all other numbers.
 impl Ord for Value {
     fn cmp(&self, other: &Self) -> Ordering {
-        self.partial_cmp(other).unwrap()
+        match (self, other) {
+            (Value::Atom(a), _) => a.borrow().cmp(other),
+            (_, Value::Atom(b)) => self.cmp(&b.borrow()),
+            (Value::LazySeq(a, _), _) => match (realized_list(a), other) {
+                (Ok(a), _) => a.cmp(other),
+                (Err(_), Value::LazySeq(b, _)) if realized_list(b).is_err() => {
+                    Rc::as_ptr(a).cmp(&Rc::as_ptr(b))
+                }
+                (Err(_), _) => Ordering::Greater,
+            },
+            (_, Value::LazySeq(b, _)) => match realized_list(b) {
+                Ok(b) => self.cmp(&b),
+                Err(_) => Ordering::Less,
+            },
+            (a, b) if a.type_rank() != b.type_rank() => a.type_rank().cmp(&b.type_rank()),
+            (Value::List(_, _) | Value::Vec(_, _), Value::List(_, _) | Value::Vec(_, _)) => self
+                .try_iter_list_or_vec()
+                .unwrap()
+                .cmp(other.try_iter_list_or_vec().unwrap()),
+            (Value::Map(_, _) | Value::SortedMap(_, _), _) => {
+                let mut a: Vec<_> = self.try_iter_map().unwrap().collect();
+                let mut b: Vec<_> = other.try_iter_map().unwrap().collect();
+                a.sort_unstable_by(|x, y| x.0.cmp(y.0));
+                b.sort_unstable_by(|x, y| x.0.cmp(y.0));
+                a.cmp(&b)
+            }
+            (Value::Set(_, _) | Value::SortedSet(_, _), _) => {
+                let mut a: Vec<_> = self.try_iter_set().unwrap().collect();
+                let mut b: Vec<_> = other.try_iter_set().unwrap().collect();
+                a.sort_unstable();
+                b.sort_unstable();
+                a.cmp(&b)
+            }
+            (Value::Symbol(a), Value::Symbol(b)) | (Value::Keyword(a), Value::Keyword(b)) => {
+                a.cmp(b)
+            }
+            (Value::String(a), Value::String(b)) => a.cmp(b),
+            (Value::Char(a), Value::Char(b)) => a.cmp(b),
+            (Value::Regex(a), Value::Regex(b)) => a.as_str().cmp(b.as_str()),
+            (Value::HostFn(a, _), Value::HostFn(b, _)) => a.cmp(b),
+            (Value::Closure(a, _), Value::Closure(b, _)) => Rc::as_ptr(a).cmp(&Rc::as_ptr(b)),
+            (Value::Bool(a), Value::Bool(b)) => a.cmp(b),
+            (Value::Nil, Value::Nil) => Ordering::Equal,
+            (a, b) => match a.numeric_cmp(b) {
+                Some(Ordering::Equal) | None => {
+                    let is_nan = |v: &Value| matches!(v, Value::Float(f) if f.is_nan());
+                    is_nan(a)
+                        .cmp(&is_nan(b))
+                        .then(a.number_rank().cmp(&b.number_rank()))
+                }
+                Some(ordering) => ordering,
+            },
+        }
+    }
+}
+
+impl PartialOrd for Value {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
 impl PartialEq for Value {
     fn eq(&self, other: &Self) -> bool {
-        match (
-            self.clone().deref_atom_recursively(),
-            other.clone().deref_atom_recursively(),
-        ) {
-            (Value::List(a, _), Value::List(b, _))
-            | (Value::Vec(a, _), Value::Vec(b, _))
-            | (Value::List(a, _), Value::Vec(b, _))
-            | (Value::Vec(a, _), Value::List(b, _)) => a == b,
+        match (self, other) {
+            (Value::Atom(a), _) => *a.borrow() == *other,
+            (_, Value::Atom(b)) => *self == *b.borrow(),
+            (Value::LazySeq(a, _), _) => match realized_list(a) {
+                Ok(a) => a == *other,
+                Err(_) => matches!(other, Value::LazySeq(b, _) if Rc::ptr_eq(a, b)),
+            },
+            (_, Value::LazySeq(b, _)) => realized_list(b).is_ok_and(|b| *self == b),
+            (Value::List(a, _), Value::List(b, _)) => a == b,
+            (Value::Vec(a, _), Value::Vec(b, _)) => a == b,
+            (a @ Value::List(_, _), b @ Value::Vec(_, _))
+            | (a @ Value::Vec(_, _), b @ Value::List(_, _)) => {
+                let (a, b) = (a.try_iter_list_or_vec(), b.try_iter_list_or_vec());
+                a.unwrap().eq(b.unwrap())
+            }
             (Value::Map(a, _), Value::Map(b, _)) => a == b,
+            (Value::Set(a, _), Value::Set(b, _)) => a == b,
+            (
+                Value::Map(_, _) | Value::SortedMap(_, _),
+                Value::Map(_, _) | Value::SortedMap(_, _),
+            ) => {
+                // look up the entries in a hash map, because comparing keys in a sorted map
+                // may require calling its comparator
+                let (sorted, other) = match self {
+                    Value::SortedMap(_, _) => (self, other),
+                    _ => (other, self),
+                };
+                let lookup = match other {
+                    Value::Map(map, _) => Cow::Borrowed(map),
+                    other => Cow::Owned(
+                        other
+                            .try_iter_map()
+                            .unwrap()
+                            .map(|(k, v)| (k.clone(), v.clone()))
+                            .collect::<PersistentHashMap<_, _>>(),
+                    ),
+                };
+                sorted.count() == lookup.len()
+                    && sorted
+                        .try_iter_map()
+                        .unwrap()
+                        .all(|(k, v)| lookup.get(k) == Some(v))
+            }
+            (
+                Value::Set(_, _) | Value::SortedSet(_, _),
+                Value::Set(_, _) | Value::SortedSet(_, _),
+            ) => {
+                let (sorted, other) = match self {
+                    Value::SortedSet(_, _) => (self, other),
+                    _ => (other, self),
+                };
+                let lookup = match other {
+                    Value::Set(set, _) => Cow::Borrowed(set),
+                    other => Cow::Owned(
+                        other
+                            .try_iter_set()
+                            .unwrap()
+                            .cloned()
+                            .collect::<PersistentHashSet<_>>(),
+                    ),
+                };
+                sorted.count() == lookup.len()
+                    && sorted.try_iter_set().unwrap().all(|v| lookup.contains(v))
+            }
             (Value::Symbol(a), Value::Symbol(b)) => a == b,
             (Value::Keyword(a), Value::Keyword(b)) => a == b,
             (Value::String(a), Value::String(b)) => a == b,
+            (Value::Char(a), Value::Char(b)) => a == b,
+            (Value::Regex(a), Value::Regex(b)) => a.as_str() == b.as_str(),
             (Value::HostFn(a, _), Value::HostFn(b, _)) => a == b,
-            (Value::Closure(a, _), Value::Closure(b, _)) => Rc::ptr_eq(&a, &b),
+            (Value::Closure(a, _), Value::Closure(b, _)) => Rc::ptr_eq(a, b),
             (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::Ratio(a), Value::Ratio(b)) => a == b,
+            (Value::Float(a), Value::Float(b)) => a == b,
             (Value::Nil, Value::Nil) => true,
             (Value::Bool(a), Value::Bool(b)) => a == b,
             _ => false,
@@ -235,3 +776,138 @@ impl PartialEq for Value {
     }
 }
 impl Eq for Value {}
+
+/// The elements of a lazy sequence as a list, to compare or hash it like one. Callers
+/// realize their arguments first to report errors. A sequence that fails to realize anyway
+/// is only equal to itself, and is ordered after all other values.
+fn realized_list(seq: &Rc<LazySeq>) -> RuntimeResult<Value> {
+    Ok(Value::List(
+        lazy_seq::realized_elements(seq)?,
+        Box::new(Value::Nil),
+    ))
+}
+
+/// Combines the hashes of `items` independently of their order.
+fn unordered_hash<T: Hash>(items: impl Iterator<Item = T>) -> u64 {
+    items.fold(0u64, |sum, item| {
+        let mut hasher = FxHasher::default();
+        item.hash(&mut hasher);
+        sum.wrapping_add(hasher.finish())
+    })
+}
+
+/// Hashes consistently with `=`: lists and vectors with the same elements hash the same,
+/// and maps and sets hash independently of the order of their entries.
+impl Hash for Value {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Value::Atom(a) => return a.borrow().hash(state),
+            Value::LazySeq(seq, _) => {
+                return match realized_list(seq) {
+                    Ok(list) => list.hash(state),
+                    Err(_) => Rc::as_ptr(seq).hash(state),
+                }
+            }
+            _ => {}
+        }
+        self.type_rank().hash(state);
+        match self {
+            Value::List(_, _) | Value::Vec(_, _) => {
+                let elements = self.try_iter_list_or_vec().unwrap();
+                elements.len().hash(state);
+                elements.for_each(|v| v.hash(state));
+            }
+            Value::Map(_, _) | Value::SortedMap(_, _) => {
+                self.count().hash(state);
+                unordered_hash(self.try_iter_map().unwrap()).hash(state);
+            }
+            Value::Set(_, _) | Value::SortedSet(_, _) => {
+                self.count().hash(state);
+                unordered_hash(self.try_iter_set().unwrap()).hash(state);
+            }
+            Value::Number(n) => n.hash(state),
+            Value::Ratio(r) => r.hash(state),
+            // 0.0 and -0.0 are equal
+            Value::Float(f) => (if *f == 0.0 { 0.0 } else { *f }).to_bits().hash(state),
+            Value::Symbol(s) | Value::Keyword(s) => s.hash(state),
+            Value::String(s) => s.hash(state),
+            Value::Char(c) => c.hash(state),
+            Value::Regex(r) => r.as_str().hash(state),
+            Value::HostFn(f, _) => f.hash(state),
+            Value::Closure(c, _) => Rc::as_ptr(c).hash(state),
+            Value::Bool(b) => b.hash(state),
+            Value::Nil | Value::Atom(_) | Value::LazySeq(_, _) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::{Hash, Hasher};
+
+    use crate::reader::read_str;
+
+    #[test]
+    fn total_order() {
+        let sorted = [
+            "nil", "false", "true", "##-Inf", "-1", "1/2", "0.5", "1", "1.0", "##Inf", "##NaN",
+            "\"a\"", "\"b\"", "a", ":a", "()", "[1 2]", "(1 2 3)", "{}",
+        ]
+        .iter()
+        .map(|s| read_str(s).unwrap())
+        .collect::<Vec<_>>();
+        for (i, a) in sorted.iter().enumerate() {
+            for (j, b) in sorted.iter().enumerate() {
+                assert_eq!(a.cmp(b), i.cmp(&j), "{} <=> {}", a, b);
+            }
+        }
+    }
+
+    #[test]
+    fn hash_consistent_with_eq() {
+        let hash = |v: &super::Value| {
+            let mut hasher = rustc_hash::FxHasher::default();
+            v.hash(&mut hasher);
+            hasher.finish()
+        };
+        for (a, b) in [
+            ("[1 (2 3)]", "(1 [2 3])"),
+            ("{:a 1 [2] 3}", "{(2) 3 :a 1}"),
+            ("0.0", "-0.0"),
+            ("2/4", "1/2"),
+            ("99999999999999999999", "99999999999999999999"),
+        ] {
+            let (a, b) = (read_str(a).unwrap(), read_str(b).unwrap());
+            assert_eq!(a, b);
+            assert_eq!(hash(&a), hash(&b), "{} {}", a, b);
+        }
+    }
+
+    #[test]
+    fn seq_operations() {
+        let read = |s: &str| read_str(s).unwrap();
+        for (coll, count, first) in [
+            ("nil", 0, "nil"),
+            ("()", 0, "nil"),
+            ("[1 2 3]", 3, "1"),
+            ("\"abc\"", 3, "\\a"),
+            ("{:a 1}", 1, "[:a 1]"),
+            ("#{:a}", 1, ":a"),
+        ] {
+            let coll = read(coll);
+            assert_eq!(coll.seq_count().unwrap(), count, "{}", coll);
+            assert_eq!(coll.seq_is_empty().unwrap(), count == 0, "{}", coll);
+            assert_eq!(coll.clone().seq_first().unwrap(), read(first), "{}", coll);
+            assert_eq!(
+                coll.clone().seq_rest().unwrap().seq_count().unwrap(),
+                count.saturating_sub(1)
+            );
+        }
+        assert_eq!(
+            read("\"abc\"").seq_nth(&2usize.into()).unwrap(),
+            read("\\c")
+        );
+        assert!(read("[1 2]").seq_nth(&2usize.into()).is_err());
+        assert!(read("1").seq_count().is_err());
+    }
+}
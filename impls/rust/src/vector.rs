@@ -0,0 +1,424 @@
+//! A persistent vector, implemented as a bit-partitioned trie like Clojure's `PersistentVector`.
+//!
+//! Elements are stored in leaves of 32 elements, and the last (up to 32) elements are kept
+//! in a separate tail, so that `push` is usually just an append to the tail.
+//! All updates copy only the path from the root to the changed leaf, and nodes that aren't
+//! shared with another version are updated in place.
+
+use std::{fmt, iter::FromIterator, ops::Range, rc::Rc};
+
+const BITS: usize = 5;
+const WIDTH: usize = 1 << BITS;
+const MASK: usize = WIDTH - 1;
+
+#[derive(Clone)]
+enum Node<T> {
+    Branch(Vec<Rc<Node<T>>>),
+    Leaf(Vec<T>),
+}
+
+impl<T: Clone> Node<T> {
+    fn children_mut(&mut self) -> &mut Vec<Rc<Node<T>>> {
+        match self {
+            Node::Branch(children) => children,
+            Node::Leaf(_) => unreachable!("leaves only occur at level 0"),
+        }
+    }
+
+    fn values(&self) -> &[T] {
+        match self {
+            Node::Leaf(values) => values,
+            Node::Branch(_) => unreachable!("branches only occur above level 0"),
+        }
+    }
+}
+
+/// The trie holding all elements. A `PersistentVector` is a view of a range of it,
+/// which makes `subvec` cheap.
+#[derive(Clone)]
+struct Trie<T> {
+    len: usize,
+    shift: usize,
+    root: Rc<Node<T>>,
+    tail: Rc<Vec<T>>,
+}
+
+impl<T: Clone> Trie<T> {
+    fn new() -> Self {
+        Self {
+            len: 0,
+            shift: BITS,
+            root: Rc::new(Node::Branch(Vec::new())),
+            tail: Rc::new(Vec::new()),
+        }
+    }
+
+    fn tail_offset(&self) -> usize {
+        if self.len < WIDTH {
+            0
+        } else {
+            ((self.len - 1) >> BITS) << BITS
+        }
+    }
+
+    /// The leaf (or tail) containing the element at `index`.
+    fn chunk(&self, index: usize) -> &[T] {
+        if index >= self.tail_offset() {
+            return &self.tail;
+        }
+        let mut node = &self.root;
+        let mut level = self.shift;
+        while level > 0 {
+            match &**node {
+                Node::Branch(children) => node = &children[(index >> level) & MASK],
+                Node::Leaf(_) => unreachable!("leaves only occur at level 0"),
+            }
+            level -= BITS;
+        }
+        node.values()
+    }
+
+    fn get(&self, index: usize) -> &T {
+        &self.chunk(index)[index & MASK]
+    }
+
+    fn push(&mut self, value: T) {
+        if self.len - self.tail_offset() < WIDTH {
+            Rc::make_mut(&mut self.tail).push(value);
+            self.len += 1;
+            return;
+        }
+        let tail = std::mem::replace(&mut self.tail, Rc::new(vec![value]));
+        let leaf = Rc::new(Node::Leaf(
+            Rc::try_unwrap(tail).unwrap_or_else(|tail| (*tail).clone()),
+        ));
+        if (self.len >> BITS) > (1 << self.shift) {
+            // the trie is full, add a level
+            let path = new_path(self.shift, leaf);
+            let old_root = std::mem::replace(&mut self.root, Rc::new(Node::Branch(Vec::new())));
+            self.root = Rc::new(Node::Branch(vec![old_root, path]));
+            self.shift += BITS;
+        } else {
+            push_leaf(self.len, self.shift, &mut self.root, leaf);
+        }
+        self.len += 1;
+    }
+
+    fn set(&mut self, index: usize, value: T) {
+        if index >= self.tail_offset() {
+            Rc::make_mut(&mut self.tail)[index & MASK] = value;
+            return;
+        }
+        let mut node = &mut self.root;
+        let mut level = self.shift;
+        while level > 0 {
+            node = &mut Rc::make_mut(node).children_mut()[(index >> level) & MASK];
+            level -= BITS;
+        }
+        match Rc::make_mut(node) {
+            Node::Leaf(values) => values[index & MASK] = value,
+            Node::Branch(_) => unreachable!("branches only occur above level 0"),
+        }
+    }
+
+    fn pop(&mut self) {
+        if self.len - self.tail_offset() > 1 {
+            Rc::make_mut(&mut self.tail).pop();
+            self.len -= 1;
+            return;
+        }
+        if self.len == 1 {
+            *self = Self::new();
+            return;
+        }
+        // the tail becomes empty, so the last leaf moves out of the trie to become the new tail
+        self.tail = Rc::new(self.chunk(self.len - 2).to_vec());
+        pop_leaf(self.len, self.shift, &mut self.root);
+        self.len -= 1;
+        if self.shift > BITS {
+            if let Node::Branch(children) = &*self.root {
+                if children.len() == 1 {
+                    self.root = children[0].clone();
+                    self.shift -= BITS;
+                }
+            }
+        }
+    }
+}
+
+fn new_path<T>(level: usize, leaf: Rc<Node<T>>) -> Rc<Node<T>> {
+    if level == 0 {
+        leaf
+    } else {
+        Rc::new(Node::Branch(vec![new_path(level - BITS, leaf)]))
+    }
+}
+
+/// Adds a full leaf to the trie, which holds `len` elements (including the tail) before.
+fn push_leaf<T: Clone>(len: usize, level: usize, node: &mut Rc<Node<T>>, leaf: Rc<Node<T>>) {
+    let children = Rc::make_mut(node).children_mut();
+    let index = ((len - 1) >> level) & MASK;
+    if level == BITS {
+        children.push(leaf);
+    } else if index < children.len() {
+        push_leaf(len, level - BITS, &mut children[index], leaf);
+    } else {
+        children.push(new_path(level - BITS, leaf));
+    }
+}
+
+/// Removes the last leaf from the trie, which holds `len` elements (including the tail).
+/// Returns whether `node` is empty afterwards.
+fn pop_leaf<T: Clone>(len: usize, level: usize, node: &mut Rc<Node<T>>) -> bool {
+    let children = Rc::make_mut(node).children_mut();
+    let index = ((len - 2) >> level) & MASK;
+    if level > BITS {
+        if pop_leaf(len, level - BITS, &mut children[index]) {
+            children.pop();
+        }
+    } else {
+        children.pop();
+    }
+    children.is_empty()
+}
+
+/// An immutable vector with cheap copies, `push`, `pop`, `set` and `subvec`.
+#[derive(Clone)]
+pub struct PersistentVector<T> {
+    trie: Trie<T>,
+    // the range of the trie that belongs to this vector
+    start: usize,
+    end: usize,
+}
+
+impl<T: Clone> PersistentVector<T> {
+    pub fn new() -> Self {
+        Self {
+            trie: Trie::new(),
+            start: 0,
+            end: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.end - self.start
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index < self.len() {
+            Some(self.trie.get(self.start + index))
+        } else {
+            None
+        }
+    }
+
+    pub fn first(&self) -> Option<&T> {
+        self.get(0)
+    }
+
+    pub fn last(&self) -> Option<&T> {
+        self.len().checked_sub(1).and_then(|i| self.get(i))
+    }
+
+    pub fn push(&mut self, value: T) {
+        if self.end == self.trie.len {
+            self.trie.push(value);
+        } else {
+            // this is a subvector, the element after it can just be overwritten
+            self.trie.set(self.end, value);
+        }
+        self.end += 1;
+    }
+
+    /// Removes the last element. Does nothing if the vector is empty.
+    pub fn pop(&mut self) {
+        if self.is_empty() {
+            return;
+        }
+        if self.end == self.trie.len {
+            self.trie.pop();
+        }
+        self.end -= 1;
+    }
+
+    /// Replaces the element at `index`, which must be in bounds.
+    pub fn set(&mut self, index: usize, value: T) {
+        assert!(index < self.len(), "index out of bounds");
+        self.trie.set(self.start + index, value);
+    }
+
+    /// A vector of the elements in `range`, which must be in bounds.
+    /// It shares all elements with this vector.
+    pub fn subvec(&self, range: Range<usize>) -> Self {
+        assert!(
+            range.start <= range.end && range.end <= self.len(),
+            "range out of bounds"
+        );
+        if range.start == range.end {
+            return Self::new();
+        }
+        Self {
+            trie: self.trie.clone(),
+            start: self.start + range.start,
+            end: self.start + range.end,
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter {
+            trie: &self.trie,
+            index: self.start,
+            end: self.end,
+            chunk: &[],
+        }
+    }
+}
+
+impl<T: Clone> Default for PersistentVector<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Clone> FromIterator<T> for PersistentVector<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut vec = Self::new();
+        vec.extend(iter);
+        vec
+    }
+}
+
+impl<T: Clone> Extend<T> for PersistentVector<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for value in iter {
+            self.push(value);
+        }
+    }
+}
+
+impl<T: Clone> From<Vec<T>> for PersistentVector<T> {
+    fn from(vec: Vec<T>) -> Self {
+        vec.into_iter().collect()
+    }
+}
+
+impl<T: Clone + PartialEq> PartialEq for PersistentVector<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Clone + fmt::Debug> fmt::Debug for PersistentVector<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+pub struct Iter<'a, T> {
+    trie: &'a Trie<T>,
+    index: usize,
+    end: usize,
+    // the rest of the current leaf, starting at `index`
+    chunk: &'a [T],
+}
+
+impl<'a, T: Clone> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        if self.index >= self.end {
+            return None;
+        }
+        if self.chunk.is_empty() {
+            self.chunk = &self.trie.chunk(self.index)[self.index & MASK..];
+        }
+        let (first, rest) = self.chunk.split_first().unwrap();
+        self.chunk = rest;
+        self.index += 1;
+        Some(first)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (self.end - self.index, Some(self.end - self.index))
+    }
+}
+
+impl<'a, T: Clone> DoubleEndedIterator for Iter<'a, T> {
+    fn next_back(&mut self) -> Option<&'a T> {
+        if self.index >= self.end {
+            return None;
+        }
+        self.end -= 1;
+        Some(self.trie.get(self.end))
+    }
+}
+
+impl<'a, T: Clone> ExactSizeIterator for Iter<'a, T> {}
+
+impl<'a, T: Clone> IntoIterator for &'a PersistentVector<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn push_get_pop() {
+        let mut vec = PersistentVector::new();
+        for i in 0..40_000 {
+            vec.push(i);
+        }
+        assert_eq!(vec.len(), 40_000);
+        assert!(vec.iter().copied().eq(0..40_000));
+        assert!(vec.iter().rev().copied().eq((0..40_000).rev()));
+        let snapshot = vec.clone();
+        for i in (0..40_000).rev() {
+            assert_eq!(vec.last(), Some(&i));
+            vec.pop();
+        }
+        assert!(vec.is_empty());
+        assert_eq!(snapshot.get(1234), Some(&1234));
+        assert_eq!(snapshot.get(40_000), None);
+    }
+
+    #[test]
+    fn structural_sharing() {
+        let original: PersistentVector<_> = (0..1000).collect();
+        let mut changed = original.clone();
+        changed.set(500, -1);
+        changed.set(999, -1);
+        changed.push(1000);
+        assert_eq!(original.get(500), Some(&500));
+        assert_eq!(original.get(999), Some(&999));
+        assert_eq!(original.len(), 1000);
+        assert_eq!(changed.get(500), Some(&-1));
+        assert_eq!(changed.get(999), Some(&-1));
+        assert_eq!(changed.len(), 1001);
+    }
+
+    #[test]
+    fn subvec() {
+        let vec: PersistentVector<_> = (0..100).collect();
+        let mut sub = vec.subvec(10..20);
+        assert!(sub.iter().copied().eq(10..20));
+        sub.push(-1);
+        sub.set(0, -2);
+        assert_eq!(sub.len(), 11);
+        assert_eq!(sub.first(), Some(&-2));
+        assert_eq!(sub.last(), Some(&-1));
+        assert!(vec.iter().copied().eq(0..100));
+        sub.pop();
+        sub.pop();
+        assert!(sub.iter().skip(1).copied().eq(11..19));
+    }
+}
@@ -0,0 +1,480 @@
+//! Executes the bytecode produced by `compiler`.
+//!
+//! Calls between mal functions push frames onto the VM's own stack instead of recursing,
+//! and tail calls replace the current frame. Calling a mal function from Rust, like `map`
+//! does, runs a new VM, so errors cross into Rust code as `Err` and are caught again by the
+//! handlers of the VM that called it.
+
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    bytecode::{Capture, Function, Op},
+    compiler,
+    env::Env,
+    hash_map::PersistentHashMap,
+    hash_set::PersistentHashSet,
+    lazy_seq, mal_core,
+    runtime_errors::{self, RuntimeResult},
+    symbol::Symbol,
+    value::{Closure, HostFn, Value},
+};
+
+/// Limits recursion that isn't in tail position, which would otherwise use up all memory.
+const MAX_FRAMES: usize = 1 << 20;
+
+/// Evaluates a form in the global environment `env`.
+///
+/// Like in Clojure, the forms of a top-level `do` are compiled and evaluated one after
+/// another, so that a macro defined by one of them can be used by the next ones.
+/// `load-file` relies on this.
+pub fn eval(form: Value, env: Rc<RefCell<Env>>) -> RuntimeResult<Value> {
+    let form = compiler::macroexpand(form, &env)?;
+    match form {
+        Value::List(l, _) if matches!(l.first(), Some(Value::Symbol(s)) if s == "do") => {
+            let mut result = Value::Nil;
+            for form in l.into_iter().skip(1) {
+                result = eval(form, env.clone())?;
+            }
+            Ok(result)
+        }
+        form => {
+            let function = compiler::compile(form, &env)?;
+            let closure = Closure {
+                function,
+                upvalues: Vec::new(),
+                is_macro: false,
+            };
+            eval_fn_no_tco(
+                Value::Closure(Rc::new(closure), Box::new(Value::Nil)),
+                Vec::new(),
+                env,
+            )
+        }
+    }
+}
+
+/// Calls a function with arguments that are already evaluated.
+pub fn eval_fn_no_tco(fun: Value, args: Vec<Value>, env: Rc<RefCell<Env>>) -> RuntimeResult<Value> {
+    if let Value::HostFn(HostFn::ByPtr(f), _) = &fun {
+        return (f.0)(args.into_iter(), env);
+    }
+    let mut vm = Vm {
+        stack: Vec::with_capacity(args.len() + 1),
+        frames: Vec::new(),
+        handlers: Vec::new(),
+        globals: env,
+    };
+    vm.stack.push(fun);
+    vm.stack.extend(args);
+    if vm.call(0, false)? {
+        vm.run()
+    } else {
+        Ok(vm.stack.pop().unwrap())
+    }
+}
+
+struct Frame {
+    closure: Rc<Closure>,
+    ip: usize,
+    /// The position of the first slot on the stack. The function is just below it.
+    base: usize,
+}
+
+struct Handler {
+    frames: usize,
+    stack: usize,
+    catch: usize,
+}
+
+struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    handlers: Vec<Handler>,
+    globals: Rc<RefCell<Env>>,
+}
+
+impl Vm {
+    /// Runs until the first frame returns, continuing at the innermost handler on errors.
+    fn run(&mut self) -> RuntimeResult<Value> {
+        loop {
+            match self.execute() {
+                Ok(value) => return Ok(value),
+                Err(err) => {
+                    let handler = self.handlers.pop().ok_or(err.clone())?;
+                    self.frames.truncate(handler.frames);
+                    self.stack.truncate(handler.stack);
+                    self.stack.push(err);
+                    self.frames.last_mut().unwrap().ip = handler.catch;
+                }
+            }
+        }
+    }
+
+    fn execute(&mut self) -> RuntimeResult<Value> {
+        let frame = self.frames.last().unwrap();
+        let (mut closure, mut ip, mut base) = (frame.closure.clone(), frame.ip, frame.base);
+        loop {
+            let op = closure.function.code[ip];
+            ip += 1;
+            match op {
+                Op::Constant(i) => self
+                    .stack
+                    .push(closure.function.constants[i as usize].clone()),
+                Op::Nil => self.stack.push(Value::Nil),
+                Op::GetLocal(slot) => {
+                    let v = self.stack[base + slot as usize].clone();
+                    self.stack.push(v);
+                }
+                Op::GetBoxedLocal(slot) => {
+                    let v = unbox(&self.stack[base + slot as usize]);
+                    self.stack.push(v);
+                }
+                Op::GetUpvalue(i) => self.stack.push(closure.upvalues[i as usize].clone()),
+                Op::GetBoxedUpvalue(i) => self.stack.push(unbox(&closure.upvalues[i as usize])),
+                Op::GetGlobal(i) => {
+                    let name = symbol_name(&closure.function.constants[i as usize]);
+                    self.stack.push(Env::get(&self.globals, name)?);
+                }
+                Op::SetLocal(slot) => {
+                    let v = self.stack.pop().unwrap();
+                    self.stack[base + slot as usize] = v;
+                }
+                Op::NewBox(slot) => {
+                    self.stack[base + slot as usize] =
+                        Value::Atom(Rc::new(RefCell::new(Value::Nil)))
+                }
+                Op::SetBox(slot) => {
+                    let v = self.stack.pop().unwrap();
+                    match &self.stack[base + slot as usize] {
+                        Value::Atom(cell) => *cell.borrow_mut() = v,
+                        _ => unreachable!("slot is not boxed"),
+                    }
+                }
+                Op::DefGlobal(i) => {
+                    let name = symbol_name(&closure.function.constants[i as usize]);
+                    let v = self.stack.last().unwrap().clone();
+                    self.globals.borrow_mut().set(name.clone(), v);
+                }
+                Op::DefMacro(i) => {
+                    let name = symbol_name(&closure.function.constants[i as usize]);
+                    let v = match self.stack.pop().unwrap() {
+                        Value::Closure(c, meta) => {
+                            let mut c = c.as_ref().clone();
+                            c.is_macro = true;
+                            Value::Closure(Rc::new(c), meta)
+                        }
+                        v => return Err(runtime_errors::not_a("function", &v)),
+                    };
+                    self.globals.borrow_mut().set(name.clone(), v.clone());
+                    self.stack.push(v);
+                }
+                Op::Pop => {
+                    self.stack.pop();
+                }
+                Op::Jump(target) => ip = target as usize,
+                Op::JumpIfFalse(target) => {
+                    if matches!(self.stack.pop().unwrap(), Value::Bool(false) | Value::Nil) {
+                        ip = target as usize;
+                    }
+                }
+                Op::Closure(i) => {
+                    let c = self.make_closure(&closure, base, i);
+                    self.stack
+                        .push(Value::Closure(Rc::new(c), Box::new(Value::Nil)));
+                }
+                Op::LazySeq(i) => {
+                    let c = Value::Closure(
+                        Rc::new(self.make_closure(&closure, base, i)),
+                        Box::new(Value::Nil),
+                    );
+                    let env = self.globals.clone();
+                    self.stack
+                        .push(lazy_seq::lazy(move || eval_fn_no_tco(c, Vec::new(), env)));
+                }
+                Op::Vector(n) => {
+                    let items = self.stack.split_off(self.stack.len() - n as usize);
+                    self.stack
+                        .push(Value::Vec(items.into(), Box::new(Value::Nil)));
+                }
+                Op::Map(n) => {
+                    let items = self.stack.split_off(self.stack.len() - 2 * n as usize);
+                    let mut map = PersistentHashMap::new();
+                    let mut items = items.into_iter();
+                    while let Some(k) = items.next() {
+                        map.insert(k, items.next().unwrap());
+                    }
+                    self.stack.push(Value::Map(map, Box::new(Value::Nil)));
+                }
+                Op::Set(n) => {
+                    let items = self.stack.split_off(self.stack.len() - n as usize);
+                    let set: PersistentHashSet<_> = items.into_iter().collect();
+                    self.stack.push(Value::Set(set, Box::new(Value::Nil)));
+                }
+                Op::Try(catch) => {
+                    self.frames.last_mut().unwrap().ip = ip;
+                    self.handlers.push(Handler {
+                        frames: self.frames.len(),
+                        stack: self.stack.len(),
+                        catch: catch as usize,
+                    });
+                }
+                Op::EndTry => {
+                    self.handlers.pop();
+                }
+                Op::Call(argc) | Op::TailCall(argc) => {
+                    let tail = matches!(op, Op::TailCall(_));
+                    self.frames.last_mut().unwrap().ip = ip;
+                    let pushed_frame = self.call(self.stack.len() - argc as usize - 1, tail)?;
+                    if tail && !pushed_frame {
+                        if let Some(result) = self.return_from_frame() {
+                            return Ok(result);
+                        }
+                    }
+                    let frame = self.frames.last().unwrap();
+                    closure = frame.closure.clone();
+                    ip = frame.ip;
+                    base = frame.base;
+                }
+                Op::Return => {
+                    if let Some(result) = self.return_from_frame() {
+                        return Ok(result);
+                    }
+                    let frame = self.frames.last().unwrap();
+                    closure = frame.closure.clone();
+                    ip = frame.ip;
+                    base = frame.base;
+                }
+            }
+        }
+    }
+
+    /// Pops the current frame and pushes the value it returns, or returns the value if it
+    /// was the first frame.
+    fn return_from_frame(&mut self) -> Option<Value> {
+        let result = self.stack.pop().unwrap();
+        let frame = self.frames.pop().unwrap();
+        self.stack.truncate(frame.base - 1);
+        if self.frames.is_empty() {
+            Some(result)
+        } else {
+            self.stack.push(result);
+            None
+        }
+    }
+
+    fn make_closure(&self, parent: &Closure, base: usize, index: u32) -> Closure {
+        let function = parent.function.functions[index as usize].clone();
+        let upvalues = function
+            .captures
+            .iter()
+            .map(|capture| match *capture {
+                Capture::Local(slot) => self.stack[base + slot as usize].clone(),
+                Capture::Upvalue(i) => parent.upvalues[i as usize].clone(),
+            })
+            .collect();
+        Closure {
+            function,
+            upvalues,
+            is_macro: false,
+        }
+    }
+
+    /// Calls the function at `callee` with the arguments above it. Mal functions get a new
+    /// frame (replacing the current one for tail calls), and `true` is returned. Other
+    /// functions are called right away, and their result replaces the function and arguments.
+    fn call(&mut self, callee: usize, tail: bool) -> RuntimeResult<bool> {
+        loop {
+            match &self.stack[callee] {
+                Value::Closure(c, _) => {
+                    let closure = c.clone();
+                    self.bind_args(&closure.function, callee)?;
+                    let mut base = callee + 1;
+                    if tail {
+                        let old = self.frames.pop().unwrap();
+                        self.stack.drain(old.base - 1..callee);
+                        base = old.base;
+                    }
+                    if self.frames.len() == MAX_FRAMES {
+                        return Err(runtime_errors::error_to_string("stack overflow"));
+                    }
+                    self.stack.resize(base + closure.function.slots, Value::Nil);
+                    self.frames.push(Frame {
+                        closure,
+                        ip: 0,
+                        base,
+                    });
+                    return Ok(true);
+                }
+                Value::HostFn(HostFn::Apply, _) => {
+                    if self.stack.len() - callee == 1 {
+                        return Err(runtime_errors::wrong_arity("apply", 0));
+                    }
+                    self.stack.remove(callee);
+                    if self.stack.len() - callee > 1 {
+                        let last = self.stack.pop().unwrap();
+                        self.stack.extend(last.seq_elements()?);
+                    }
+                }
+                _ => {
+                    let mut args = self.stack.split_off(callee + 1).into_iter();
+                    let result = match self.stack.pop().unwrap() {
+                        Value::HostFn(HostFn::ByPtr(f), _) => (f.0)(args, self.globals.clone())?,
+                        Value::HostFn(HostFn::Eval(env), _) => {
+                            ensure_one_arg(&args, "eval")?;
+                            eval(args.next().unwrap(), env)?
+                        }
+                        Value::HostFn(HostFn::ReadLine(rl), _) => {
+                            ensure_one_arg(&args, "readline")?;
+                            let mut rl = rl.borrow_mut();
+                            match rl.readline(args.next().unwrap().try_as_str()?) {
+                                Ok(mut string) => {
+                                    if string.ends_with('\n') {
+                                        string.pop();
+                                    }
+                                    rl.add_history_entry(&string);
+                                    Value::String(string)
+                                }
+                                Err(_) => Value::Nil,
+                            }
+                        }
+                        coll => mal_core::call_collection(coll, args, &self.globals)?,
+                    };
+                    self.stack.push(result);
+                    return Ok(false);
+                }
+            }
+        }
+    }
+
+    /// Checks the number of arguments and collects the rest arguments into a list.
+    fn bind_args(&mut self, function: &Function, callee: usize) -> RuntimeResult<()> {
+        let argc = self.stack.len() - callee - 1;
+        if function.variadic && argc >= function.arity {
+            let rest = self.stack.split_off(callee + 1 + function.arity);
+            self.stack.push(Value::List(rest, Box::new(Value::Nil)));
+            Ok(())
+        } else if !function.variadic && argc == function.arity {
+            Ok(())
+        } else {
+            Err(runtime_errors::wrong_arity("function", argc))
+        }
+    }
+}
+
+fn ensure_one_arg(args: &impl ExactSizeIterator, name: &str) -> RuntimeResult<()> {
+    if args.len() == 1 {
+        Ok(())
+    } else {
+        Err(runtime_errors::wrong_arity(name, args.len()))
+    }
+}
+
+fn symbol_name(v: &Value) -> &Symbol {
+    match v {
+        Value::Symbol(s) => s,
+        _ => unreachable!("not a symbol"),
+    }
+}
+
+fn unbox(v: &Value) -> Value {
+    match v {
+        Value::Atom(cell) => cell.borrow().clone(),
+        _ => unreachable!("not a box"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{mal_core, reader::read_str};
+
+    fn eval_str(input: &str) -> RuntimeResult<Value> {
+        let env = Rc::new(RefCell::new(Env::new(None)));
+        mal_core::init_env(&mut env.borrow_mut());
+        eval(read_str(input).unwrap(), env)
+    }
+
+    fn assert_evals_to(input: &str, expected: &str) {
+        assert_eq!(
+            eval_str(input).unwrap(),
+            read_str(expected).unwrap(),
+            "{}",
+            input
+        );
+    }
+
+    #[test]
+    fn tail_calls_and_recursion() {
+        assert_evals_to(
+            "(do (def! f (fn* [n acc] (if (= n 0) acc (f (- n 1) (+ acc 1))))) (f 200000 0))",
+            "200000",
+        );
+        assert_evals_to(
+            "(do (def! f (fn* [n] (if (= n 0) 0 (+ 1 (f (- n 1)))))) (f 100000))",
+            "100000",
+        );
+        assert!(eval_str("(do (def! f (fn* [] (+ 1 (f)))) (f))").is_err());
+    }
+
+    #[test]
+    fn closures() {
+        assert_evals_to(
+            "((((fn* [a] (fn* [b] (fn* [c] (list a b c)))) 1) 2) 3)",
+            "(1 2 3)",
+        );
+        // closures in bindings can use later bindings, but not before they are initialized
+        assert_evals_to(
+            "(let* (even (fn* [n] (if (= n 0) true (odd (- n 1)))) \
+                    odd (fn* [n] (if (= n 0) false (even (- n 1))))) \
+               (even 10))",
+            "true",
+        );
+        assert_evals_to("(let* (x 1) (let* (x (+ x 1) f (fn* [] x)) (f)))", "2");
+        assert_evals_to("((fn* [& more] more) 1 2)", "(1 2)");
+        assert!(eval_str("((fn* [a] a))").is_err());
+    }
+
+    #[test]
+    fn errors_are_caught() {
+        assert_evals_to("(try* (throw 1) (catch* e (+ e 1)))", "2");
+        assert_evals_to(
+            "(let* (a 1) (try* (map (fn* [x] (throw (+ x a))) [1]) (catch* e e)))",
+            "2",
+        );
+        assert_evals_to(
+            "(try* (do (try* (throw 1) (catch* e (throw (+ e 1)))) 5) (catch* e e))",
+            "2",
+        );
+        assert!(eval_str("(try* (throw 1))").is_err());
+    }
+
+    #[test]
+    fn host_functions_check_arity() {
+        let env = Rc::new(RefCell::new(Env::new(None)));
+        mal_core::init_env(&mut env.borrow_mut());
+        let rl = Rc::new(RefCell::new(rustyline::Editor::<()>::new()));
+        let mut b = env.borrow_mut();
+        b.set(
+            "eval",
+            Value::HostFn(HostFn::Eval(env.clone()), Box::new(Value::Nil)),
+        );
+        b.set(
+            "readline",
+            Value::HostFn(HostFn::ReadLine(rl), Box::new(Value::Nil)),
+        );
+        drop(b);
+        for input in [
+            "(apply)",
+            "(eval)",
+            "(eval 1 2)",
+            "(readline)",
+            "(readline \"a\" \"b\")",
+        ] {
+            let form = read_str(input).unwrap();
+            assert!(eval(form, env.clone()).is_err(), "{}", input);
+        }
+        assert_evals_to("(try* (apply) (catch* e 1))", "1");
+        assert_evals_to("(apply list)", "()");
+        assert_evals_to("(apply + 1 [2 3])", "6");
+    }
+}